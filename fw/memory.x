@@ -0,0 +1,8 @@
+/* STM32F303K8. The last three flash pages keep the recovery journal, statistics and settings (see src/storage.rs),
+   so the firmware image is linked below them */
+MEMORY
+{
+    FLASH (rx) : ORIGIN = 0x8000000, LENGTH = 58K
+    CCMRAM (rwx) : ORIGIN = 0x10000000, LENGTH = 4K
+    RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 12K
+}
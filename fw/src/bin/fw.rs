@@ -1,8 +1,6 @@
 #![no_main]
 #![no_std]
 
-extern crate panic_halt;
-
 use fw as _;
 
 #[rtic::app(device = stm32f3xx_hal::pac, dispatchers = [FMC])]
@@ -14,16 +12,21 @@ mod app {
     use stm32f3xx_hal::timer::{Timer, Event};
     use stm32f3xx_hal::adc;
     use fw::board::{Board, CookBtn, Lid};
-    use fw::encoder::{EncoderReaderTIM1, EncoderReaderTIM3};
+    use fw::button::GestureRecognizer;
+    use fw::encoder::{EncoderConfig, EncoderReader, StepRegime, TEMP_ACCELERATION, TIME_ACCELERATION};
     use dwt_systick_monotonic::ExtU32;
     use stm32f3xx_hal::adc::{VoltageInternalReference};
     use stm32f3xx_hal::pac::{TIM2, TIM6, TIM15};
     use fw::buzzer::BuzzerManager;
     use fw::current_sensor::{CurrentReader, CurrentSensor};
-    use fw::delay::TimDelay;
-    use fw::display::LcdDisplay;
+    use fw::delay::DwtDelay;
+    use fw::display::{LcdDisplay, Screen};
+    use fw::lcd_backend::{Hd44780Backend, LcdRefresher};
+    use fw::state::executor::OvenControlHardware;
     use fw::state::manager::StateManager;
-    use fw::state::OvenControlHardware;
+    use fw::reset::ResetCause;
+    use fw::settings::Settings;
+    use fw::storage::OvenStorage;
     use fw::temp_sensor::TempSensor;
 
     #[monotonic(binds = SysTick, default = true)]
@@ -31,7 +34,6 @@ mod app {
 
     #[shared]
     struct Shared {
-        cook_btn_debounce: bool,
         lid_debounce: bool,
         lid: Lid,
         state: StateManager,
@@ -40,14 +42,19 @@ mod app {
     #[local]
     struct Local {
         cook_btn: CookBtn,
+        cook_btn_gestures: GestureRecognizer,
         current_timer: Timer<TIM2>,
         current_reader: CurrentReader,
         state_poll_timer: Timer<TIM6>,
-        pid_timer: Timer<TIM15>
+        pid_timer: Timer<TIM15>,
+        lcd_refresher: LcdRefresher,
+        storage: OvenStorage
     }
 
     #[init]
     fn init(mut cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let reset_cause = ResetCause::take(&cx.device.RCC); //Flags are cleared, as they survive the resets
+
         //Set up the system clock
         let mut rcc = cx.device.RCC.constrain();
         let mut flash = cx.device.FLASH.constrain();
@@ -60,8 +67,8 @@ mod app {
         cx.core.DWT.enable_cycle_counter();
         let mono = DwtSystick::new(&mut cx.core.DCB, cx.core.DWT, cx.core.SYST, clocks.hclk().0);
 
-        //Configure TIM7 as a delay provider
-        let mut delay = TimDelay::new(cx.device.TIM7, clocks, &mut rcc.apb1);
+        //Cycle counter is running for the monotonic timer, so it is used for delays too
+        let mut delay = DwtDelay::new(clocks);
 
         //Configure board
         let mut board = Board::new(cx.device.GPIOA, cx.device.GPIOB, cx.device.SPI1, &mut rcc.ahb, &mut rcc.apb2, clocks, &mut delay);
@@ -76,11 +83,14 @@ mod app {
 
         //Configure temperature encoder
         let tim1 = Timer::new(cx.device.TIM1, clocks, &mut rcc.apb2);
-        let temp_encoder = EncoderReaderTIM1::new(tim1.free(), 10, 50);
+        let mut temp_encoder = EncoderReader::new(tim1.free(), EncoderConfig::default(), 10, 50);
+        temp_encoder.set_acceleration(TEMP_ACCELERATION);
 
         //Configure time encoder
         let tim3 = Timer::new(cx.device.TIM3, clocks, &mut rcc.apb1);
-        let time_encoder = EncoderReaderTIM3::new(tim3.free(), 0, 180);
+        let mut time_encoder = EncoderReader::new(tim3.free(), EncoderConfig::default(), 0, 3 * 3600); //Seconds, up to 3 hours
+        time_encoder.set_acceleration(TIME_ACCELERATION);
+        time_encoder.set_regime(StepRegime{threshold: 600, fine: 10, coarse: 60}); //Seconds are only needed for short tasks like toasting
 
         //Configure drive current sensor
         let mut current_timer = Timer::new(cx.device.TIM2, clocks, &mut rcc.apb1);
@@ -92,15 +102,11 @@ mod app {
         let adc_current = adc::Adc::new(adc_pair_current.0, adc::config::Config::default(), &clocks, &adc_common_current).into_oneshot();
 
         //Configure the interrrupts
-        syscfg.select_exti_interrupt_source(&board.cook_btn);
-        board.cook_btn.trigger_on_edge(&mut exti, Edge::Rising);
-        board.cook_btn.enable_interrupt(&mut exti);
         syscfg.select_exti_interrupt_source(&board.lid);
         board.lid.trigger_on_edge(&mut exti, Edge::Falling);
         board.lid.enable_interrupt(&mut exti);
 
         unsafe {
-            NVIC::unmask(board.cook_btn.interrupt());
             NVIC::unmask(board.lid.interrupt());
             NVIC::unmask(current_timer.interrupt());
             NVIC::unmask(state_poll_timer.interrupt());
@@ -108,16 +114,27 @@ mod app {
         };
 
         //Configure control structs
-        let display_manager = LcdDisplay::new(board.lcd, delay);
+        let display_manager = LcdDisplay::new(Screen::default());
+        let lcd_refresher = LcdRefresher::new(Hd44780Backend::new(board.lcd, delay));
         let temp_sensor = TempSensor::new(board.tc_cs, board.tc_spi);
         let buzzer = BuzzerManager::new(board.buzzer);
         let current_reader = CurrentReader::new(adc_current, v_in, board.current);
         let current_sensor = CurrentSensor::new();
-        let control_hardware = OvenControlHardware{display: display_manager, buzzer, cook_ld: board.cook_ld, heater: board.heater, motor: board.motor};
-        let state_manager = StateManager::new(control_hardware, current_sensor, temp_encoder, time_encoder, temp_sensor);
+        let storage = OvenStorage::default();
+        let settings = Settings::load(&storage.settings);
+        let control_hardware = OvenControlHardware{display: display_manager, buzzer, cook_ld: board.cook_ld, heater: board.heater, motor: board.motor, settings};
+        let service = board.cook_btn.is_high().unwrap_or(false); //Cook button held at the power-up
+        let mut state_manager = StateManager::new(control_hardware, current_sensor, temp_encoder, time_encoder, temp_sensor, &storage, service);
+        let panic = fw::panic::take_record();
+        if let Some(record) = &panic { //Last reset was caused by the panic
+            defmt::error!("Panic at {}:{}: {}", record.file(), record.line, record.message());
+            state_manager.notice(record.notice());
+        }
+        if panic.is_none() || reset_cause != ResetCause::Software { //Panic handler restarts by the software reset, it is reported already
+            state_manager.on_reset(reset_cause);
+        }
 
         let shared = Shared {
-            cook_btn_debounce: false,
             lid_debounce: false,
             lid: board.lid,
             state: state_manager
@@ -125,50 +142,50 @@ mod app {
 
         let local = Local {
             cook_btn: board.cook_btn,
+            cook_btn_gestures: GestureRecognizer::default(),
             current_timer,
             current_reader,
             state_poll_timer,
-            pid_timer
+            pid_timer,
+            lcd_refresher,
+            storage
         };
 
+        lcd_refresh::spawn().unwrap();
+        storage_write::spawn().unwrap();
+
         (shared, local, init::Monotonics(mono))
     }
 
-    #[task(binds = TIM2, local = [current_timer, current_reader], shared=[state])]
+    /// Monotonic time for the cooking timer, wraps in 49 days
+    fn now_millis() -> u32 {
+        monotonics::now().duration_since_epoch().to_millis() as u32
+    }
+
+    /// Current timer also samples the cook button, gestures need its 10ms period
+    #[task(binds = TIM2, priority = 2, local = [current_timer, current_reader, cook_btn, cook_btn_gestures], shared=[state])]
     fn current_timer_handle(mut cx: current_timer_handle::Context) {
         // TODO ADC should be triggered by timer directly,
         // and use DMA to read both channels in sequence
         // but i'm lazy and it is fast enough to not to cause any issues
         cx.local.current_timer.clear_events();
-        cx.shared.state.lock(|state| state.adc_poll(cx.local.current_reader.read()));
-    }
-
-    #[task(binds = EXTI0, local = [cook_btn], shared = [cook_btn_debounce,state])]
-    fn cook_btn_handler(cx: cook_btn_handler::Context) {
-        cx.local.cook_btn.clear_interrupt();
-        let cook_btn_handler::SharedResources { mut cook_btn_debounce, mut state } = cx.shared;
-        cook_btn_debounce.lock(|debounce|
-            if !*debounce {
-                *debounce = true;
-                state.lock(|s| s.on_cook_btn());
-                cook_btn_debounce::spawn_after(300.millis()).unwrap();
+        let gesture = cx.local.cook_btn_gestures.poll(cx.local.cook_btn.is_high().unwrap_or(false));
+        cx.shared.state.lock(|state| {
+            state.adc_poll(cx.local.current_reader.read());
+            if let Some(gesture) = gesture {
+                state.on_cook_btn(gesture);
             }
-        );
-    }
-
-    #[task(shared = [cook_btn_debounce])]
-    fn cook_btn_debounce(mut cx: cook_btn_debounce::Context) {
-        cx.shared.cook_btn_debounce.lock(|debounce| *debounce = false);
+        });
     }
 
-    #[task(binds = EXTI9_5, shared = [lid, lid_debounce, state])]
+    #[task(binds = EXTI9_5, priority = 2, shared = [lid, lid_debounce, state])]
     fn lid_handler(cx: lid_handler::Context) {
         let lid_handler::SharedResources { mut lid, mut lid_debounce, mut state } = cx.shared;
         lid.lock(|l| l.clear_interrupt());
         lid_debounce.lock(|debounce|
             if !*debounce {
                 *debounce = true;
-                state.lock(|s| s.enc_poll(false));
+                state.lock(|s| s.on_lid(false));
                 lid_debounce::spawn_after(100.millis()).unwrap();
             }
         );
@@ -179,18 +196,36 @@ mod app {
         cx.shared.lid_debounce.lock(|debounce| *debounce = false);
     }
 
-    #[task(binds = TIM6_DACUNDER, local = [state_poll_timer], shared = [state, lid])]
+    #[task(binds = TIM6_DACUNDER, priority = 2, local = [state_poll_timer], shared = [state, lid])]
     fn state_timer_handler(cx: state_timer_handler::Context) {
         cx.local.state_poll_timer.clear_events();
         let state_timer_handler::SharedResources { state, lid } = cx.shared;
         (state, lid).lock(|state, lid| {
-            state.enc_poll(lid.is_low().unwrap_or(false));
+            state.enc_poll(lid.is_low().unwrap_or(false), now_millis());
         });
     }
 
-    #[task(binds = TIM1_BRK_TIM15, local = [pid_timer], shared = [state])]
+    #[task(binds = TIM1_BRK_TIM15, priority = 2, local = [pid_timer], shared = [state])]
     fn pid_timer_handler(mut cx: pid_timer_handler::Context) {
         cx.local.pid_timer.clear_events();
         cx.shared.state.lock(|state| state.pid_poll())
     }
-}
\ No newline at end of file
+
+    /// LCD is slow, so the screen is rendered to memory by the state machine and copied to the LCD
+    /// by the lowest priority task, that can't delay sensors and heater control
+    #[task(priority = 1, local = [lcd_refresher], shared = [state])]
+    fn lcd_refresh(mut cx: lcd_refresh::Context) {
+        let screen = cx.shared.state.lock(|state| state.screen());
+        cx.local.lcd_refresher.refresh(&screen);
+        lcd_refresh::spawn_after(50.millis()).unwrap();
+    }
+
+    /// Flash page erase stalls the CPU for tens of milliseconds, so the records are written by the lowest priority task,
+    /// outside of the state lock and never in the middle of a transition
+    #[task(priority = 1, local = [storage], shared = [state])]
+    fn storage_write(mut cx: storage_write::Context) {
+        let writes = cx.shared.state.lock(|state| state.take_writes());
+        writes.store(cx.local.storage);
+        storage_write::spawn_after(100.millis()).unwrap();
+    }
+}
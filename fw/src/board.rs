@@ -1,11 +1,10 @@
-use hd44780_driver::bus::FourBitBus;
-use hd44780_driver::{Cursor, HD44780};
 use stm32f3xx_hal::gpio::{Alternate, Analog, GpioExt, Input, OpenDrain, Output, PA0, PA1, PA10, PA11, PA12, PA15, PA2, PA3, PA4, PA5, PA6, PA7, PA8, PA9, PB0, PB1, PB3, PB4, PB5, PB6, PB7, PushPull};
 use stm32f3xx_hal::hal::blocking::delay::{DelayMs, DelayUs};
 use stm32f3xx_hal::pac::{GPIOA, GPIOB, SPI1};
 use stm32f3xx_hal::rcc::{AHB, APB2, Clocks};
 use stm32f3xx_hal::spi::Spi;
 use stm32f3xx_hal::prelude::*;
+use crate::lcd::{FourBitBus, Hd44780};
 
 pub type CookBtn = PA0<Input>;
 pub type Lid = PB6<Input>;
@@ -25,7 +24,7 @@ pub type HeaterEnable = PA12<Output<PushPull>>;
 
 pub type SpiBus = Spi<SPI1, (TcSck, TcSo, TcSi), u8>;
 
-type Hd4BitBus = FourBitBus<
+pub type LCD = Hd44780<
     PA15<Output<PushPull>>,
     PB7<Output<PushPull>>,
     PB3<Output<PushPull>>,
@@ -34,8 +33,6 @@ type Hd4BitBus = FourBitBus<
     PA3<Output<PushPull>>
 >;
 
-pub type LCD = HD44780<Hd4BitBus>;
-
 pub struct Board {
     pub cook_btn: CookBtn,
     pub lid: Lid,
@@ -81,20 +78,7 @@ impl Board {
         let lcd_d7 = port_b.pb0.into_push_pull_output(&mut port_b.moder, &mut port_b.otyper);
         let lcd_d8 = port_a.pa3.into_push_pull_output(&mut port_a.moder, &mut port_a.otyper);
 
-        let lcd = HD44780::new_4bit(lcd_rs, lcd_e, lcd_d5, lcd_d6, lcd_d7, lcd_d8, delay)
-            .and_then(|mut l| {
-                l.reset(delay)?;
-                Result::Ok(l)
-            })
-            .and_then(|mut l| {
-                l.clear(delay)?;
-                Result::Ok(l)
-            })
-            .and_then(|mut l| {
-                l.set_cursor_visibility(Cursor::Invisible, delay)?;
-                Result::Ok(l)
-            })
-            .unwrap();
+        let lcd = Hd44780::new_4bit(FourBitBus::from_pins(lcd_rs, lcd_e, lcd_d5, lcd_d6, lcd_d7, lcd_d8), delay);
 
         let tc_cs = port_a.pa4.into_push_pull_output(&mut port_a.moder, &mut port_a.otyper);
         let tc_sck = port_a.pa5.into_af_push_pull::<5>(&mut port_a.moder, &mut port_a.otyper, &mut port_a.afrl);
@@ -0,0 +1,146 @@
+//Button is sampled every 10ms
+const DEBOUNCE_SAMPLES: u8 = 3; //Level has to be stable for 30ms to be accepted
+const LONG_PRESS_SAMPLES: u16 = 100; //1s
+const DOUBLE_PRESS_SAMPLES: u16 = 30; //Second press has to come in 300ms after the release
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Gesture {
+    /// Press and release, reported after the double press window is over
+    Short,
+    /// Button held for a second, reported without waiting for the release
+    Long,
+    /// Second press shortly after the short one, reported on the second press
+    Double
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PressState {
+    Idle,
+    Pressed(u16),
+    Released(u16),
+    WaitRelease //Gesture is already reported
+}
+
+/**
+Recognizes short, long and double presses from the sampled button level.
+
+Level is debounced by requiring it to be stable for a few samples,
+so it can be polled from any periodic task without the pin interrupt.
+ */
+pub struct GestureRecognizer {
+    level: bool,
+    unstable: u8,
+    state: PressState
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        GestureRecognizer { level: false, unstable: 0, state: PressState::Idle }
+    }
+}
+
+impl GestureRecognizer {
+    /// Debounces the raw level, returns true if the accepted level was changed
+    fn debounce(&mut self, pressed: bool) -> bool {
+        if pressed == self.level {
+            self.unstable = 0;
+            return false;
+        }
+        self.unstable += 1;
+        if self.unstable < DEBOUNCE_SAMPLES {
+            return false;
+        }
+        self.unstable = 0;
+        self.level = pressed;
+        true
+    }
+
+    /// Should be called every 10ms with the raw button level
+    pub fn poll(&mut self, pressed: bool) -> Option<Gesture> {
+        let changed = self.debounce(pressed);
+        let (state, gesture) = match self.state {
+            PressState::Idle if changed && self.level => (PressState::Pressed(0), None),
+            PressState::Idle => (PressState::Idle, None),
+            PressState::Pressed(_) if changed => (PressState::Released(0), None),
+            PressState::Pressed(samples) if samples + 1 >= LONG_PRESS_SAMPLES => (PressState::WaitRelease, Some(Gesture::Long)),
+            PressState::Pressed(samples) => (PressState::Pressed(samples + 1), None),
+            PressState::Released(_) if changed => (PressState::WaitRelease, Some(Gesture::Double)),
+            PressState::Released(samples) if samples + 1 >= DOUBLE_PRESS_SAMPLES => (PressState::Idle, Some(Gesture::Short)),
+            PressState::Released(samples) => (PressState::Released(samples + 1), None),
+            PressState::WaitRelease if changed && !self.level => (PressState::Idle, None),
+            PressState::WaitRelease => (PressState::WaitRelease, None)
+        };
+        self.state = state;
+        gesture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use super::*;
+
+    /// Polls the same level for the number of samples, returns the reported gestures
+    fn feed(recognizer: &mut GestureRecognizer, pressed: bool, samples: usize) -> Vec<Gesture> {
+        (0..samples).filter_map(|_| recognizer.poll(pressed)).collect()
+    }
+
+    #[test]
+    fn bounce_is_ignored() {
+        let mut recognizer = GestureRecognizer::default();
+        for _ in 0..10 {
+            assert!(feed(&mut recognizer, true, DEBOUNCE_SAMPLES as usize - 1).is_empty());
+            assert!(feed(&mut recognizer, false, 1).is_empty());
+        }
+        assert!(feed(&mut recognizer, false, 200).is_empty(), "level was never stable");
+    }
+
+    #[test]
+    fn short_press_after_double_press_window() {
+        let mut recognizer = GestureRecognizer::default();
+        assert!(feed(&mut recognizer, true, 10).is_empty());
+        assert!(feed(&mut recognizer, false, 32).is_empty(), "second press may still come");
+        assert!(feed(&mut recognizer, false, 1) == [Gesture::Short]);
+        assert!(feed(&mut recognizer, false, 100).is_empty());
+    }
+
+    #[test]
+    fn long_press_without_release() {
+        let mut recognizer = GestureRecognizer::default();
+        assert!(feed(&mut recognizer, true, 102).is_empty());
+        assert!(feed(&mut recognizer, true, 1) == [Gesture::Long], "reported at 100 samples after the debounce");
+        assert!(feed(&mut recognizer, true, 300).is_empty());
+        assert!(feed(&mut recognizer, false, 100).is_empty(), "release is swallowed");
+    }
+
+    #[test]
+    fn double_press() {
+        let mut recognizer = GestureRecognizer::default();
+        assert!(feed(&mut recognizer, true, 10).is_empty());
+        assert!(feed(&mut recognizer, false, 20).is_empty());
+        assert!(feed(&mut recognizer, true, 2).is_empty());
+        assert!(feed(&mut recognizer, true, 1) == [Gesture::Double], "reported on the second press");
+        assert!(feed(&mut recognizer, false, 100).is_empty(), "no short press follows");
+    }
+
+    #[test]
+    fn late_second_press_is_a_new_one() {
+        let mut recognizer = GestureRecognizer::default();
+        feed(&mut recognizer, true, 10);
+        assert!(feed(&mut recognizer, false, 40) == [Gesture::Short]);
+        assert!(feed(&mut recognizer, true, 10).is_empty());
+        assert!(feed(&mut recognizer, false, 40) == [Gesture::Short]);
+    }
+
+    #[test]
+    fn bounce_during_long_hold() {
+        let mut recognizer = GestureRecognizer::default();
+        assert!(feed(&mut recognizer, true, 50).is_empty());
+        assert!(feed(&mut recognizer, false, DEBOUNCE_SAMPLES as usize - 1).is_empty());
+        assert!(feed(&mut recognizer, true, 50).is_empty(), "hold isn't restarted by the bounce");
+        assert!(feed(&mut recognizer, true, 1) == [Gesture::Long]);
+        assert!(feed(&mut recognizer, false, 1).is_empty());
+        assert!(feed(&mut recognizer, true, 1).is_empty());
+        assert!(feed(&mut recognizer, false, 100).is_empty());
+    }
+}
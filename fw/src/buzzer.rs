@@ -1,43 +1,289 @@
-use stm32f3xx_hal::hal::digital::v2::OutputPin;
+use heapless::Deque;
+use embedded_hal::digital::v2::OutputPin;
 
+const QUEUE_SIZE: usize = 4;
+
+/**
+Part of the buzzer pattern: buzzer state for a number of the 100ms ticks.
+ */
+#[derive(Clone, Copy)]
+pub struct Segment {
+    on: bool,
+    ticks: u8
+}
+
+const fn on(ticks: u8) -> Segment {
+    Segment { on: true, ticks }
+}
+
+const fn off(ticks: u8) -> Segment {
+    Segment { on: false, ticks }
+}
+
+pub type Pattern = &'static [Segment];
+
+const START: Pattern = &[on(3)];
+const PRE_DONE: Pattern = &[on(10)];
+const DONE: Pattern = &[on(25)];
+const STAGE_CHANGE: Pattern = &[on(2), off(2), on(2)];
+const WARNING: Pattern = &[on(1), off(1), on(1), off(1), on(1), off(5)];
+//Faults are reported by the number of long beeps
+const FAULT_TEMP_SENSOR: Pattern = &[on(5), off(15)];
+const FAULT_OVERHEAT: Pattern = &[on(5), off(3), on(5), off(15)];
+const FAULT_CURRENT_SENSOR: Pattern = &[on(5), off(3), on(5), off(3), on(5), off(15)];
+const FAULT_MOTOR_CONTROL: Pattern = &[on(5), off(3), on(5), off(3), on(5), off(3), on(5), off(15)];
+const FAULT_MOTOR_FAILURE: Pattern = &[on(5), off(3), on(5), off(3), on(5), off(3), on(5), off(3), on(5), off(15)];
+const FAULT_MOTOR_OVERLOAD: Pattern = &[on(5), off(3), on(5), off(3), on(5), off(3), on(5), off(3), on(5), off(3), on(5), off(15)];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    TempSensor,
+    Overheat,
+    CurrentSensor,
+    MotorControl,
+    MotorFailure,
+    MotorOverload
+}
+
+impl Fault {
+    const ALL: [Fault; 6] = [Fault::TempSensor, Fault::Overheat, Fault::CurrentSensor, Fault::MotorControl, Fault::MotorFailure, Fault::MotorOverload];
+
+    /// Code of the fault in the fault log
+    pub fn code(&self) -> u16 {
+        *self as u16 + 1 //Zero is for no fault logged
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Fault::ALL.into_iter().find(|fault| fault.code() == code)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::TempSensor => "Temp sensor",
+            Fault::Overheat => "Overheat",
+            Fault::CurrentSensor => "Current sensor",
+            Fault::MotorControl => "Motor control",
+            Fault::MotorFailure => "Motor failure",
+            Fault::MotorOverload => "Motor overload"
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Sound {
+    /// Short beep on `run` button call
+    Start,
+    /// pre-cook beep, on minute to done
+    PreDone,
+    /// Cooking is done
+    Done,
+    /// Cooking moved to the next stage, like end of the preheating
+    StageChange,
+    Warning,
+    /// Played even when sound is muted
+    Fault(Fault)
+}
+
+impl Sound {
+    fn pattern(&self) -> Pattern {
+        match self {
+            Sound::Start => START,
+            Sound::PreDone => PRE_DONE,
+            Sound::Done => DONE,
+            Sound::StageChange => STAGE_CHANGE,
+            Sound::Warning => WARNING,
+            Sound::Fault(Fault::TempSensor) => FAULT_TEMP_SENSOR,
+            Sound::Fault(Fault::Overheat) => FAULT_OVERHEAT,
+            Sound::Fault(Fault::CurrentSensor) => FAULT_CURRENT_SENSOR,
+            Sound::Fault(Fault::MotorControl) => FAULT_MOTOR_CONTROL,
+            Sound::Fault(Fault::MotorFailure) => FAULT_MOTOR_FAILURE,
+            Sound::Fault(Fault::MotorOverload) => FAULT_MOTOR_OVERLOAD
+        }
+    }
+}
+
+/**
+Plays queued patterns one after another, advanced by the state poll timer.
+ */
 pub struct BuzzerManager<T: OutputPin> {
     buzzer: T,
-    periods: u32
+    queue: Deque<Pattern, QUEUE_SIZE>,
+    segment: usize,
+    ticks: u8,
+    muted: bool
 }
 
 impl<T:OutputPin> BuzzerManager<T> {
     pub fn new(buzzer: T) -> Self {
-        Self{buzzer, periods: 0}
+        Self{buzzer, queue: Deque::new(), segment: 0, ticks: 0, muted: false}
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
     }
 
     pub fn on_timer(&mut self) {
-        if self.periods > 0 {
-            self.periods -= 1;
-            if self.periods == 0 {
-                self.buzzer.set_high().ok();
+        if self.ticks > 0 {
+            self.ticks -= 1;
+            if self.ticks == 0 {
+                self.segment += 1;
+                self.advance();
+            }
+        }
+    }
+
+    /// Starts the current segment, moving to the next pattern when the current one is over
+    fn advance(&mut self) {
+        while let Some(pattern) = self.queue.front() {
+            if let Some(segment) = pattern.get(self.segment) {
+                self.ticks = segment.ticks;
+                if segment.on {
+                    self.buzzer.set_low().ok(); //Speaker output is inverted
+                } else {
+                    self.buzzer.set_high().ok();
+                }
+                return;
             }
+            self.queue.pop_front();
+            self.segment = 0;
         }
+        self.buzzer.set_high().ok();
     }
 
-    fn beep(&mut self, duration: u32) {
-        if self.periods == 0 {
-            self.periods = duration;
+    /// Queues the sound, it is dropped if the queue is full
+    pub fn play(&mut self, sound: Sound) {
+        if self.muted && !matches!(sound, Sound::Fault(_)) {
+            return;
+        }
+        if self.queue.push_back(sound.pattern()).is_ok() && self.ticks == 0 {
+            self.advance();
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// Drives the speaker directly, stopping the sounds. Only used by the service mode.
+    pub fn set_output(&mut self, on: bool) {
+        self.stop();
+        if on {
             self.buzzer.set_low().ok();
         }
     }
 
-    /// Short beep on `run` button call
-    pub fn run_beep(&mut self) {
-        self.beep(3)
+    /// Stops the current and all queued sounds
+    pub fn stop(&mut self) {
+        self.queue.clear();
+        self.segment = 0;
+        self.ticks = 0;
+        self.buzzer.set_high().ok();
     }
+}
 
-    /// pre-cook beep, on minute to done
-    pub fn pre_beep(&mut self) {
-        self.beep(10)
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+    use core::convert::Infallible;
+    use super::*;
+
+    const SOUNDS: [Sound; 11] = [Sound::Start, Sound::PreDone, Sound::Done, Sound::StageChange, Sound::Warning, Sound::Fault(Fault::TempSensor), Sound::Fault(Fault::Overheat),
+        Sound::Fault(Fault::CurrentSensor), Sound::Fault(Fault::MotorControl), Sound::Fault(Fault::MotorFailure), Sound::Fault(Fault::MotorOverload)];
+    const SILENCE: Pattern = &[off(1)]; //Speaker stays off after the sounds
+
+    /**
+    Speaker output, inverted as on the board.
+     */
+    #[derive(Default)]
+    struct MockPin {
+        sounding: bool
     }
 
-    /// Cooking is done
-    pub fn done_beep(&mut self) {
-        self.beep(25)
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.sounding = true;
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.sounding = false;
+            Ok(())
+        }
+    }
+
+    fn buzzer() -> BuzzerManager<MockPin> {
+        BuzzerManager::new(MockPin::default())
+    }
+
+    /// Speaker state at each of the following ticks, starting with the current one
+    fn trace(buzzer: &mut BuzzerManager<MockPin>, ticks: usize) -> Vec<bool> {
+        (0..ticks).map(|_| {
+            let sounding = buzzer.buzzer.sounding;
+            buzzer.on_timer();
+            sounding
+        }).collect()
+    }
+
+    /// Speaker states the patterns are made of, one per tick
+    fn expected(patterns: &[Pattern]) -> Vec<bool> {
+        patterns.iter().flat_map(|pattern| pattern.iter()).flat_map(|segment| (0..segment.ticks).map(|_| segment.on)).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn patterns_follow_segments() {
+        for sound in SOUNDS {
+            let mut buzzer = buzzer();
+            let mut timing = expected(&[sound.pattern()]);
+            buzzer.play(sound);
+            timing.extend([false; 3]);
+            assert_eq!(trace(&mut buzzer, timing.len()), timing);
+            assert!(!buzzer.is_playing());
+        }
+    }
+
+    #[test]
+    fn sounds_are_played_in_turn() {
+        let mut buzzer = buzzer();
+        buzzer.play(Sound::StageChange);
+        let mut timing = trace(&mut buzzer, 3);
+        buzzer.play(Sound::Start);
+        timing.extend(trace(&mut buzzer, 7));
+        assert_eq!(timing, expected(&[STAGE_CHANGE, START, SILENCE]));
+    }
+
+    #[test]
+    fn full_queue_drops_sound() {
+        let mut buzzer = buzzer();
+        (0..QUEUE_SIZE).for_each(|_| buzzer.play(Sound::StageChange));
+        buzzer.play(Sound::Done);
+        let timing = expected(&[STAGE_CHANGE; QUEUE_SIZE]);
+        assert_eq!(trace(&mut buzzer, timing.len()), timing);
+        assert!(!buzzer.is_playing() && !buzzer.buzzer.sounding);
+    }
+
+    #[test]
+    fn stop_silences_queue() {
+        let mut buzzer = buzzer();
+        buzzer.play(Sound::Done);
+        buzzer.play(Sound::PreDone);
+        trace(&mut buzzer, 5);
+        buzzer.stop();
+        assert!(!buzzer.is_playing());
+        assert_eq!(trace(&mut buzzer, 40), [false; 40]);
+        buzzer.play(Sound::Start); //Next sound starts afresh
+        assert_eq!(trace(&mut buzzer, 4), expected(&[START, SILENCE]));
+    }
+
+    #[test]
+    fn muted_plays_faults_only() {
+        let mut buzzer = buzzer();
+        buzzer.set_muted(true);
+        buzzer.play(Sound::Done);
+        assert!(!buzzer.is_playing() && !buzzer.buzzer.sounding);
+        buzzer.play(Sound::Fault(Fault::Overheat));
+        let timing = expected(&[FAULT_OVERHEAT]);
+        assert_eq!(trace(&mut buzzer, timing.len()), timing);
+    }
+}
@@ -1,18 +1,25 @@
+#[cfg(not(test))]
 use core::ptr;
 use heapless::Deque;
+#[cfg(not(test))]
 use stm32f3xx_hal::adc::{Adc, OneShot, VoltageInternalReference};
+#[cfg(not(test))]
 use stm32f3xx_hal::pac::{ADC1, ADC1_2};
+#[cfg(not(test))]
 use stm32f3xx_hal::prelude::_embedded_hal_adc_OneShot;
+#[cfg(not(test))]
 use crate::board;
 
 type ValuesRing = Deque<f32, 10>;
 
+#[cfg(not(test))]
 pub struct CurrentReader {
     adc_current: Adc<ADC1, OneShot>,
     v_in: VoltageInternalReference<ADC1_2>,
     current_pin: board::Current,
 }
 
+#[cfg(not(test))]
 impl CurrentReader {
     pub fn new(adc_current: Adc<ADC1, OneShot>, v_in: VoltageInternalReference<ADC1_2>, current_pin: board::Current) -> Self {
         CurrentReader{adc_current, v_in, current_pin}
@@ -29,10 +36,11 @@ impl CurrentReader {
 
 pub struct CurrentSensor {
     sensor_values: ValuesRing,
+    last: Option<f32> //Latest raw sample, volts
 }
 
 fn average(values: &ValuesRing) -> Option<f32>{
-    if values.is_empty() || values.len() < 100 {
+    if !values.is_full() {
         None
     } else {
         Some(values.iter().sum::<f32>()/values.len() as f32)
@@ -41,10 +49,11 @@ fn average(values: &ValuesRing) -> Option<f32>{
 
 impl CurrentSensor {
     pub fn new() -> Self {
-        CurrentSensor {sensor_values: Deque::new()}
+        CurrentSensor {sensor_values: Deque::new(), last: None}
     }
 
     pub fn add_value(&mut self, volts: f32) {
+        self.last = Some(volts);
         if self.sensor_values.is_full() {
             self.sensor_values.pop_front();
         }
@@ -65,6 +74,11 @@ impl CurrentSensor {
         }*/
     }
 
+    /// Latest raw sample, none before the first ADC reading
+    pub fn millivolts(&self) -> Option<i16> {
+        self.last.map(|volts| (volts * 1000.0) as i16)
+    }
+
     pub fn get_sensor(&self) -> f32 {
         average(&self.sensor_values).unwrap_or(-1.0)
     }
@@ -91,4 +105,28 @@ impl CurrentSensor {
         false
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_data_before_first_sample() {
+        let mut sensor = CurrentSensor::new();
+        assert_eq!(sensor.millivolts(), None);
+        sensor.add_value(0.125);
+        assert_eq!(sensor.millivolts(), Some(125));
+        sensor.add_value(0.25);
+        assert_eq!(sensor.millivolts(), Some(250), "latest sample isn't filtered");
+    }
+
+    #[test]
+    fn average_needs_full_ring() {
+        let mut sensor = CurrentSensor::new();
+        (1..10).for_each(|_| sensor.add_value(0.1));
+        assert_eq!(sensor.get_sensor(), -1.0);
+        sensor.add_value(0.1);
+        assert!((sensor.get_sensor() - 0.1).abs() < 0.001);
+    }
+}
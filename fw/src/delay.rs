@@ -1,33 +1,49 @@
+use cortex_m::peripheral::DWT;
 use stm32f3xx_hal::hal::blocking::delay::{DelayMs, DelayUs};
-use stm32f3xx_hal::prelude::_embedded_hal_timer_CountDown;
-use stm32f3xx_hal::{block};
-use stm32f3xx_hal::rcc::{Clocks, RccBus};
-use stm32f3xx_hal::time::duration::Milliseconds;
-use stm32f3xx_hal::timer::{Instance, Timer};
-
-pub struct TimDelay<TIM: RccBus> {
-    timer: Timer<TIM>
+use stm32f3xx_hal::rcc::Clocks;
+
+/**
+Busy wait delay on the DWT cycle counter, that is already running for the monotonic timer.
+
+Accurate to a few CPU cycles and doesn't occupy a hardware timer. The counter wraps in about a minute at 64MHz,
+so the delays are limited to the `u16` milliseconds.
+ */
+#[derive(Clone, Copy)]
+pub struct DwtDelay {
+    cycles_per_us: u32
 }
 
-impl<TIM: RccBus + Instance> TimDelay<TIM> {
-    pub fn new(tim: TIM, clocks: Clocks, apb: &mut <TIM as RccBus>::Bus) -> Self {
-        let timer = Timer::new(tim, clocks, apb);
-        TimDelay{timer}
+impl DwtDelay {
+    pub fn new(clocks: Clocks) -> Self {
+        DwtDelay{cycles_per_us: clocks.hclk().0 / 1_000_000}
+    }
+
+    fn delay_cycles(&self, cycles: u32) {
+        let start = DWT::cycle_count();
+        while DWT::cycle_count().wrapping_sub(start) < cycles {}
     }
 }
 
-impl<TIM: RccBus + Instance> DelayMs<u8> for TimDelay<TIM> {
+impl DelayUs<u16> for DwtDelay {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_cycles(us as u32 * self.cycles_per_us)
+    }
+}
+
+impl DelayUs<u32> for DwtDelay {
+    fn delay_us(&mut self, us: u32) {
+        self.delay_cycles(us.saturating_mul(self.cycles_per_us))
+    }
+}
+
+impl DelayMs<u8> for DwtDelay {
     fn delay_ms(&mut self, ms: u8) {
-        self.timer.start(Milliseconds::new(ms as u32));
-        block!(self.timer.wait()).unwrap();
-        self.timer.stop()
+        self.delay_cycles(ms as u32 * 1000 * self.cycles_per_us)
     }
 }
 
-impl<TIM: RccBus + Instance> DelayUs<u16> for TimDelay<TIM> {
-    fn delay_us(&mut self, _: u16) {
-        self.timer.start(Milliseconds::new(1u32)); //HD44780 needs longer breaks
-        block!(self.timer.wait()).unwrap();
-        self.timer.stop()
+impl DelayMs<u16> for DwtDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_cycles(ms as u32 * 1000 * self.cycles_per_us)
     }
-}
\ No newline at end of file
+}
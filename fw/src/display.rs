@@ -1,43 +1,405 @@
 use heapless::String;
 use core::fmt::Write;
-use stm32f3xx_hal::timer;
-use crate::board;
-use crate::delay::TimDelay;
+use crate::settings::TempUnit;
 
-pub struct LcdDisplay<TIM: timer::Instance> {
-    lcd: board::LCD,
-    delay: TimDelay<TIM>,
+pub const WIDTH: usize = 16;
+pub const LINES: usize = 2;
+pub const GLYPH_SLOTS: usize = 8; //CGRAM holds 8 custom characters
+const MESSAGE_WIDTH: usize = 13; //Top line is the message followed by the three status icons
+const ICONS_POS: u8 = 13;
+const BAR_CELL_COLUMNS: u16 = 5;
+const BAR_FULL: u8 = 0xFF; //Filled block from the character ROM
+const DEGREE: u8 = 0xDF;
+const HOUR: u16 = 3600; //Seconds
+
+/**
+Custom characters. Each glyph is bound to a CGRAM slot, glyphs that are never shown together share a slot.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Glyph {
+    Heater,
+    Fan,
+    LidOpen,
+    Preheating,
+    KeepWarm,
+    BoardHot, //Board temperature warning
+    Bar(u8) //Partially filled progress bar cell, 1-4 columns
+}
+
+impl Glyph {
+    fn slot(&self) -> u8 {
+        match self {
+            Glyph::Heater => 0,
+            Glyph::Fan => 1,
+            Glyph::LidOpen | Glyph::Preheating | Glyph::KeepWarm | Glyph::BoardHot => 2,
+            Glyph::Bar(columns) => 2 + columns
+        }
+    }
+
+    fn pattern(&self) -> [u8; 8] {
+        match self {
+            Glyph::Heater => [0b00000, 0b01001, 0b10010, 0b01001, 0b10010, 0b00000, 0b11111, 0b00000],
+            Glyph::Fan => [0b00000, 0b11001, 0b01011, 0b00100, 0b11010, 0b10011, 0b00000, 0b00000],
+            Glyph::LidOpen => [0b01110, 0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b11111, 0b00000],
+            Glyph::Preheating => [0b00100, 0b01110, 0b10101, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000],
+            Glyph::KeepWarm => [0b00100, 0b01010, 0b01010, 0b01110, 0b01110, 0b11111, 0b11111, 0b01110],
+            Glyph::BoardHot => [0b00100, 0b01010, 0b01010, 0b10101, 0b10001, 0b10101, 0b11111, 0b00000],
+            Glyph::Bar(columns) => [(0b11111 << (5 - columns)) & 0b11111; 8]
+        }
+    }
+}
+
+/**
+Character display, that `LcdDisplay` renders to.
+ */
+pub trait DisplayBackend {
+    fn clear(&mut self);
+    fn set_cursor(&mut self, row: u8, column: u8);
+    fn write_byte(&mut self, data: u8);
+    /// Loads the custom character pattern, moves the cursor out of the text area
+    fn set_glyph(&mut self, slot: u8, pattern: &[u8; 8]);
+    fn set_enabled(&mut self, enabled: bool);
+
+    fn write_bytes(&mut self, data: &[u8]) {
+        data.iter().for_each(|b| self.write_byte(*b));
+    }
+}
+
+/**
+Two line display content, rendered by the state machine and pushed to the LCD by the `LcdRefresher`.
+ */
+pub type Screen = FrameBuffer<WIDTH, LINES>;
+
+/**
+In-memory display of `COLUMNS`x`ROWS` characters, keeps the screen text, custom characters and display state.
+Writes beyond the line end are dropped, like the HD44780 does with its invisible DDRAM part.
+ */
+#[derive(Clone)]
+pub struct FrameBuffer<const COLUMNS: usize, const ROWS: usize> {
+    cells: [[u8; COLUMNS]; ROWS],
+    glyphs: [[u8; 8]; GLYPH_SLOTS],
+    row: usize,
+    column: usize,
+    enabled: bool
+}
+
+impl<const COLUMNS: usize, const ROWS: usize> Default for FrameBuffer<COLUMNS, ROWS> {
+    fn default() -> Self {
+        FrameBuffer { cells: [[b' '; COLUMNS]; ROWS], glyphs: [[0; 8]; GLYPH_SLOTS], row: 0, column: 0, enabled: true }
+    }
+}
+
+impl<const COLUMNS: usize, const ROWS: usize> FrameBuffer<COLUMNS, ROWS> {
+    pub fn line(&self, row: usize) -> &[u8; COLUMNS] {
+        &self.cells[row]
+    }
+
+    pub fn glyph(&self, slot: u8) -> &[u8; 8] {
+        &self.glyphs[slot as usize & 0b0111]
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+impl<const COLUMNS: usize, const ROWS: usize> DisplayBackend for FrameBuffer<COLUMNS, ROWS> {
+    fn clear(&mut self) {
+        self.cells = [[b' '; COLUMNS]; ROWS];
+        self.row = 0;
+        self.column = 0;
+    }
+
+    fn set_cursor(&mut self, row: u8, column: u8) {
+        self.row = row as usize;
+        self.column = column as usize;
+    }
+
+    fn write_byte(&mut self, data: u8) {
+        if let Some(cell) = self.cells.get_mut(self.row).and_then(|line| line.get_mut(self.column)) {
+            *cell = data;
+        }
+        self.column += 1;
+    }
+
+    fn set_glyph(&mut self, slot: u8, pattern: &[u8; 8]) {
+        self.glyphs[slot as usize & 0b0111] = *pattern;
+        self.row = ROWS; //Cursor is in the CGRAM now
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+}
+
+/**
+Tracks CGRAM content and reloads the slot when a glyph sharing it is requested.
+ */
+struct GlyphManager {
+    slots: [Option<Glyph>; GLYPH_SLOTS]
 }
 
-impl<TIM: timer::Instance> LcdDisplay<TIM> {
-    pub fn new(mut lcd: board::LCD, mut delay: TimDelay<TIM>) -> Self {
-        lcd.clear(&mut delay).unwrap_or_default();
-        LcdDisplay { lcd, delay }
+impl GlyphManager {
+    /// Returns character code of the glyph, loading it to the CGRAM first if needed
+    fn code<B: DisplayBackend>(&mut self, backend: &mut B, glyph: Glyph) -> u8 {
+        let slot = glyph.slot();
+        if self.slots[slot as usize] != Some(glyph) {
+            backend.set_glyph(slot, &glyph.pattern());
+            self.slots[slot as usize] = Some(glyph);
+        }
+        slot
+    }
+}
+
+/**
+Status icons on the right side of the top line.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatusIcons {
+    pub heater: bool,
+    pub fan: bool,
+    pub mode: Option<Glyph>
+}
+
+/**
+Timer field of the state line.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    /// Seconds left
+    Countdown(u16),
+    /// Seconds of the manual cooking
+    Elapsed(u16),
+    /// Manual mode is chosen, but the cooking isn't started
+    Manual
+}
+
+pub struct LcdDisplay<B: DisplayBackend> {
+    backend: B,
+    glyphs: GlyphManager,
+    icons: StatusIcons,
+    progress: Option<u16>
+}
+
+impl<B: DisplayBackend> LcdDisplay<B> {
+    pub fn new(mut backend: B) -> Self {
+        backend.clear();
+        LcdDisplay { backend, glyphs: GlyphManager { slots: [None; GLYPH_SLOTS] }, icons: StatusIcons::default(), progress: None }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 
     pub fn error_message(&mut self, msg: &str) {
-        self.lcd.clear(&mut self.delay).unwrap_or_default();
-        self.lcd.set_cursor_pos(0, &mut self.delay).unwrap_or_default();
-        self.lcd.write_str(msg, &mut self.delay).unwrap_or_default();
+        self.backend.clear();
+        self.backend.set_cursor(0, 0);
+        self.backend.write_bytes(msg.as_bytes());
+        self.progress = None;
+        self.icons = StatusIcons::default();
+    }
+
+    /// Fills the whole screen, `°` is replaced with the degree sign and `█` with the filled block
+    pub fn screen(&mut self, top: &str, bottom: &str) {
+        self.write_line(0, top);
+        self.write_line(1, bottom);
+        self.progress = None;
+        self.icons = StatusIcons::default();
+    }
+
+    /// Lights every segment of every cell, to check the LCD
+    pub fn test_pattern(&mut self) {
+        for row in 0..LINES as u8 {
+            self.backend.set_cursor(row, 0);
+            self.backend.write_bytes(&[BAR_FULL; WIDTH]);
+        }
+        self.progress = None;
+        self.icons = StatusIcons::default();
+    }
+
+    fn write_line(&mut self, row: u8, text: &str) {
+        self.backend.set_cursor(row, 0);
+        let mut chars = text.chars().map(|c| match c {
+            '°' => DEGREE,
+            '█' => BAR_FULL,
+            _ => c as u8
+        });
+        for _ in 0..WIDTH {
+            self.backend.write_byte(chars.next().unwrap_or(b' '));
+        }
+    }
+
+    pub fn set_sleep(&mut self, sleep: bool) {
+        self.backend.set_enabled(!sleep);
     }
 
+    /// Shows the message on the top line, removing the progress bar and status icons
     pub fn message(&mut self, msg: &str) {
-        self.lcd.set_cursor_pos(0, &mut self.delay).unwrap_or_default();
-        self.lcd.write_str(msg, &mut self.delay).unwrap_or_default();
+        let mut output: String<MESSAGE_WIDTH> = String::new();
+        write!(output, "{:<width$.width$}", msg, width = MESSAGE_WIDTH).unwrap_or_default();
+        self.backend.set_cursor(0, 0);
+        self.backend.write_bytes(output.as_bytes());
+        self.progress = None;
+        self.draw_icons(StatusIcons::default());
+    }
+
+    pub fn icons(&mut self, icons: StatusIcons) {
+        if self.icons != icons {
+            self.draw_icons(icons);
+        }
+    }
+
+    fn draw_icons(&mut self, icons: StatusIcons) {
+        let heater = if icons.heater { self.glyphs.code(&mut self.backend, Glyph::Heater) } else { b' ' };
+        let fan = if icons.fan { self.glyphs.code(&mut self.backend, Glyph::Fan) } else { b' ' };
+        let mode = icons.mode.map(|g| self.glyphs.code(&mut self.backend, g)).unwrap_or(b' ');
+        self.backend.set_cursor(0, ICONS_POS);
+        self.backend.write_bytes(&[heater, fan, mode]);
+        self.icons = icons;
     }
 
-    pub fn state(&mut self, time: u16, temp_actual: u16, temp_requested: u16) {
+    /// Replaces the top line message with a progress bar of the elapsed time
+    pub fn progress(&mut self, elapsed: u32, total: u32) {
+        let bar_columns = MESSAGE_WIDTH as u16 * BAR_CELL_COLUMNS;
+        let columns = (elapsed.min(total) * bar_columns as u32).checked_div(total).unwrap_or(0) as u16;
+        if self.progress == Some(columns) {
+            return;
+        }
+        let mut cells = [b' '; MESSAGE_WIDTH];
+        for (index, cell) in cells.iter_mut().enumerate() {
+            let filled = columns.saturating_sub(index as u16 * BAR_CELL_COLUMNS).min(BAR_CELL_COLUMNS);
+            *cell = match filled {
+                0 => b' ',
+                BAR_CELL_COLUMNS => BAR_FULL,
+                partial => self.glyphs.code(&mut self.backend, Glyph::Bar(partial as u8))
+            };
+        }
+        self.backend.set_cursor(0, 0);
+        self.backend.write_bytes(&cells);
+        self.progress = Some(columns);
+    }
+
+    /// Shows the timer, seconds are shown below an hour
+    pub fn state(&mut self, timer: Timer, temp_actual: u16, temp_requested: u16, unit: TempUnit) {
         let mut temp_string: String<3> = String::new();
-        if temp_actual < 50 {
+        if temp_actual < unit.limits().0 {
             write!(temp_string, "{}", "---").unwrap_or_default();
         } else {
             write!(temp_string, "{:03}", temp_actual).unwrap_or_default();
         }
         let mut output: String<16> = String::new();
-        write!(output, "T{:02}:{:02} {}/{:03}", time / 60, time % 60, temp_string, temp_requested).unwrap_or_default();
-        self.lcd.set_cursor_pos(40, &mut self.delay).unwrap_or_default();
-        self.lcd.write_str(&output, &mut self.delay).unwrap_or_default();
-        self.lcd.write_byte(0xDFu8, &mut self.delay).unwrap_or_default();
-        self.lcd.write_str("t", &mut self.delay).unwrap_or_default();
+        let (prefix, time) = match timer {
+            Timer::Countdown(time) => ('T', time),
+            Timer::Elapsed(time) => ('+', time),
+            Timer::Manual => ('T', 0)
+        };
+        if timer == Timer::Manual {
+            write!(output, "{}--:-- {}/{:03}", prefix, temp_string, temp_requested).unwrap_or_default();
+        } else if time < HOUR {
+            write!(output, "{}{:02}:{:02} {}/{:03}", prefix, time / 60, time % 60, temp_string, temp_requested).unwrap_or_default();
+        } else {
+            write!(output, "{}{}h{:02}m {}/{:03}", prefix, time / HOUR, time % HOUR / 60, temp_string, temp_requested).unwrap_or_default();
+        }
+        self.backend.set_cursor(1, 0);
+        self.backend.write_bytes(output.as_bytes());
+        self.backend.write_byte(DEGREE);
+        self.backend.write_bytes(unit.symbol().as_bytes());
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl<const COLUMNS: usize, const ROWS: usize> FrameBuffer<COLUMNS, ROWS> {
+    /// Line as the readable text, custom characters are matched by their CGRAM pattern
+    pub fn text(&self, row: usize) -> std::string::String {
+        let glyphs = [(Glyph::Heater, '≈'), (Glyph::Fan, '✻'), (Glyph::LidOpen, '⊓'), (Glyph::Preheating, '↑'), (Glyph::KeepWarm, '♨'), (Glyph::BoardHot, '⚠'),
+            (Glyph::Bar(1), '▏'), (Glyph::Bar(2), '▎'), (Glyph::Bar(3), '▍'), (Glyph::Bar(4), '▌')];
+        self.cells[row].iter().map(|&code| match code {
+            DEGREE => '°',
+            BAR_FULL => '█',
+            0..=7 => glyphs.iter().find(|(glyph, _)| glyph.slot() == code && glyph.pattern() == *self.glyph(code)).map(|(_, c)| *c).unwrap_or('?'),
+            _ => code as char
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display() -> LcdDisplay<Screen> {
+        LcdDisplay::new(Screen::default())
+    }
+
+    fn lines(display: &LcdDisplay<Screen>) -> [std::string::String; LINES] {
+        [display.backend().text(0), display.backend().text(1)]
+    }
+
+    #[test]
+    fn state_line_formats() {
+        let mut display = display();
+        display.state(Timer::Countdown(90), 175, 180, TempUnit::Celsius);
+        assert_eq!(display.backend().text(1), "T01:30 175/180°C");
+        display.state(Timer::Countdown(5400), 355, 360, TempUnit::Fahrenheit);
+        assert_eq!(display.backend().text(1), "T1h30m 355/360°F");
+        display.state(Timer::Elapsed(3900), 40, 180, TempUnit::Celsius);
+        assert_eq!(display.backend().text(1), "+1h05m ---/180°C");
+        display.state(Timer::Manual, 100, 120, TempUnit::Fahrenheit);
+        assert_eq!(display.backend().text(1), "T--:-- ---/120°F");
+    }
+
+    #[test]
+    fn message_keeps_icons_area() {
+        let mut display = display();
+        display.icons(StatusIcons { heater: true, fan: true, mode: Some(Glyph::KeepWarm) });
+        assert_eq!(display.backend().text(0), "             ≈✻♨");
+        display.message("A message, longer than the area");
+        assert_eq!(display.backend().text(0), "A message, lo   ");
+    }
+
+    #[test]
+    fn shared_slot_is_reloaded() {
+        let mut display = display();
+        display.icons(StatusIcons { mode: Some(Glyph::LidOpen), ..StatusIcons::default() });
+        assert_eq!(display.backend().text(0), "               ⊓");
+        display.icons(StatusIcons { mode: Some(Glyph::BoardHot), ..StatusIcons::default() });
+        assert_eq!(display.backend().text(0), "               ⚠");
+    }
+
+    #[test]
+    fn progress_bar_cells() {
+        let mut display = display();
+        display.progress(0, 100);
+        assert_eq!(display.backend().text(0), "                ");
+        display.progress(50, 100); //32 of the 65 columns
+        assert_eq!(display.backend().text(0), "██████▎         ");
+        display.progress(200, 100);
+        assert_eq!(display.backend().text(0), "█████████████   ");
+        display.progress(1, 0);
+        assert_eq!(display.backend().text(0), "                ");
+    }
+
+    #[test]
+    fn screen_replaces_signs() {
+        let mut display = display();
+        display.screen("Board 50°C", "██");
+        assert_eq!(lines(&display), ["Board 50°C      ", "██              "]);
+        display.error_message("T SENSOR FAILURE");
+        assert_eq!(lines(&display), ["T SENSOR FAILURE", "                "]);
+    }
+
+    #[test]
+    fn test_pattern_fills_screen() {
+        let mut display = display();
+        display.message("Text");
+        display.test_pattern();
+        assert_eq!(lines(&display), ["████████████████"; LINES]);
+    }
+
+    #[test]
+    fn writes_beyond_line_are_dropped() {
+        let mut screen = Screen::default();
+        screen.set_cursor(0, 14);
+        screen.write_bytes(b"abcd");
+        assert_eq!(screen.text(0), "              ab");
+        assert_eq!(screen.text(1), "                ");
+    }
+}
@@ -1,85 +1,273 @@
+#[cfg(not(test))]
 use stm32f3xx_hal::pac::{TIM1, TIM3};
-use paste::paste;
-macro_rules! encoder_reader {
+
+/// Speed based step multipliers as (steps per poll, multiplier) pairs, sorted by the speed
+pub type AccelerationCurve = &'static [(u16, u16)];
+
+pub const NO_ACCELERATION: AccelerationCurve = &[];
+pub const TEMP_ACCELERATION: AccelerationCurve = &[(3, 2), (6, 4)];
+pub const TIME_ACCELERATION: AccelerationCurve = &[(3, 2), (5, 4), (8, 8)];
+
+/**
+Value change per encoder step: fine steps below the threshold and coarse steps above it.
+ */
+#[derive(Clone, Copy)]
+pub struct StepRegime {
+    pub threshold: u16,
+    pub fine: u16,
+    pub coarse: u16
+}
+
+impl StepRegime {
+    pub const fn linear() -> Self {
+        StepRegime { threshold: u16::MAX, fine: 1, coarse: 1 }
+    }
+
+    /// Moves the value by a single step, stopping at the threshold when crossing it
+    fn next(&self, value: u16, up: bool) -> u16 {
+        if up {
+            if value < self.threshold {
+                value.saturating_add(self.fine).min(self.threshold)
+            } else {
+                value.saturating_add(self.coarse)
+            }
+        } else if value > self.threshold {
+            value.saturating_sub(self.coarse).max(self.threshold)
+        } else {
+            value.saturating_sub(self.fine)
+        }
+    }
+}
+
+/**
+Encoder interface configuration.
+
+Timer counts `counts_per_detent * detents` values and wraps around, readings differing
+by more than `max_steps` detents are treated as an overspeed and ignored.
+ */
+#[derive(Clone, Copy)]
+pub struct EncoderConfig {
+    pub counts_per_detent: u16,
+    pub detents: u16,
+    pub filter: u8,
+    pub inverted: bool,
+    pub max_steps: u16
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig { counts_per_detent: 2, detents: 512, filter: 0b1000, inverted: false, max_steps: 50 } //Filter is fDTS/8, N=6
+    }
+}
+
+/**
+Timer, that could be used as a quadrature encoder interface.
+ */
+pub trait EncoderTimer {
+    fn configure(&mut self, config: &EncoderConfig);
+    fn count(&self) -> u16;
+    fn is_counting_down(&self) -> bool;
+}
+
+#[cfg(not(test))]
+macro_rules! encoder_timer {
     ($timer:ident) => {
-        paste! {
-            pub struct [<EncoderReader $timer>] {
-                enc: $timer,
-                prev_value: u16,
-                low_margin: u16,
-                high_margin: u16
+        impl EncoderTimer for $timer {
+            fn configure(&mut self, config: &EncoderConfig) {
+                self.smcr.modify(|_,w| w.sms().encoder_mode_1()); // Act on both channels
+                self.ccer.modify(|_,w| w.cc1p().bit(!config.inverted).cc2p().set_bit()); //On falling edge, inverted TI1 reverses the direction
+                self.ccmr1_input().modify(|_, w| w.ic1f().bits(config.filter).ic2f().bits(config.filter));
+                self.ccmr1_input().modify(|_,w| w.cc1s().ti1().cc2s().ti2());
+                self.psc.write(|w| w.psc().bits(0)); //No prescaler
+                self.arr.write(|w| w.arr().bits(config.counts_per_detent * config.detents - 1));
+                self.cnt.write(|w| w.cnt().bits(config.counts_per_detent * config.detents / 2)); //Start in the middle
+                self.cr1.modify(|_,w| w.cen().enabled()); //Start encoder interface
+            }
+
+            fn count(&self) -> u16 {
+                self.cnt.read().cnt().bits()
             }
 
-            impl [<EncoderReader $timer>] {
-                pub fn new(enc: $timer, low_margin: u16, high_margin: u16) -> Self {
-                    //Configure timer to act as encoder interface
-                    enc.smcr.modify(|_,w| w.sms().encoder_mode_1()); // Act on both channels
-                    enc.ccer.modify(|_,w| w.cc1p().set_bit().cc2p().set_bit()); //On falling edge
-                    enc.ccmr1_input().modify(|_, w| w.ic1f().fdts_div8_n6()); // Filter 8
-                    let filter_value = enc.ccmr1_input().read().ic1f().bits();
-                    enc.ccmr1_input().modify(|_, w| w.ic2f().bits(filter_value)); //Copy of filter 8
-                    enc.ccmr1_input().modify(|_,w| w.cc1s().ti1().cc2s().ti2());
-                    enc.psc.write(|w| w.psc().bits(0)); //No prescaler
-                    enc.arr.write(|w| w.arr().bits(1024)); //1024 values
-                    enc.cnt.write(|w| w.cnt().bits(512)); //Start in the middle
-                    enc.cr1.modify(|_,w| w.cen().enabled()); //Start encoder interface
-
-                    //Read the current value
-                    let prev_value = enc.cnt.read().cnt().bits()>>1;
-                    Self{enc, prev_value, low_margin, high_margin}
-                }
-
-                pub fn read(&mut self, current: u16) -> Option<u16> {
-                    let dir = self.enc.cr1.read().dir().is_down();
-                    let value = self.enc.cnt.read().cnt().bits() >> 1;
-                    let steps = get_steps(dir, self.prev_value, value);
-                    if steps != 0 {
-                        self.prev_value = value;
-                        let mut next_value = if dir {
-                            current.saturating_sub(steps)
-                        } else {
-                            current + steps
-                        };
-                        // Clamping the range
-                        if next_value < self.low_margin {
-                            next_value = self.low_margin;
-                        }
-                        if next_value > self.high_margin {
-                            next_value = self.high_margin;
-                        }
-                        Some(next_value)
-                    } else {
-                        None
-                    }
-                }
+            fn is_counting_down(&self) -> bool {
+                self.cr1.read().dir().is_down()
             }
         }
     }
 }
 
-encoder_reader!(TIM1);
-encoder_reader!(TIM3);
+#[cfg(not(test))]
+encoder_timer!(TIM1);
+#[cfg(not(test))]
+encoder_timer!(TIM3);
+
+pub struct EncoderReader<T: EncoderTimer> {
+    enc: T,
+    config: EncoderConfig,
+    prev_value: u16,
+    low_margin: u16,
+    high_margin: u16,
+    acceleration: AccelerationCurve,
+    regime: StepRegime
+}
+
+impl<T: EncoderTimer> EncoderReader<T> {
+    pub fn new(mut enc: T, config: EncoderConfig, low_margin: u16, high_margin: u16) -> Self {
+        enc.configure(&config);
+        let prev_value = enc.count() / config.counts_per_detent;
+        Self{enc, config, prev_value, low_margin, high_margin, acceleration: NO_ACCELERATION, regime: StepRegime::linear()}
+    }
+
+    pub fn set_acceleration(&mut self, acceleration: AccelerationCurve) {
+        self.acceleration = acceleration;
+    }
+
+    pub fn set_regime(&mut self, regime: StepRegime) {
+        self.regime = regime;
+    }
+
+    pub fn set_margins(&mut self, low_margin: u16, high_margin: u16) {
+        self.low_margin = low_margin;
+        self.high_margin = high_margin;
+    }
+
+    /// Signed number of detents since the last reading, for the relative inputs like menu navigation
+    pub fn read_delta(&mut self) -> i16 {
+        let dir = self.enc.is_counting_down();
+        let value = self.enc.count() / self.config.counts_per_detent;
+        let steps = get_steps(dir, self.prev_value, value, self.config.detents, self.config.max_steps) as i16;
+        if steps != 0 {
+            self.prev_value = value;
+        }
+        if dir { -steps } else { steps }
+    }
 
-fn get_steps(direction: bool, prev: u16, current: u16) -> u16 {
+    pub fn read(&mut self, current: u16) -> Option<u16> {
+        let dir = self.enc.is_counting_down();
+        let value = self.enc.count() / self.config.counts_per_detent;
+        let steps = get_steps(dir, self.prev_value, value, self.config.detents, self.config.max_steps);
+        if steps != 0 {
+            self.prev_value = value;
+            let steps = accelerate(self.acceleration, steps);
+            let mut next_value = current;
+            for _ in 0..steps {
+                next_value = self.regime.next(next_value, !dir);
+            }
+            Some(next_value.clamp(self.low_margin, self.high_margin))
+        } else {
+            None
+        }
+    }
+}
+
+fn accelerate(curve: AccelerationCurve, steps: u16) -> u16 {
+    let multiplier = curve.iter()
+        .take_while(|(speed, _)| steps >= *speed)
+        .last()
+        .map(|(_, multiplier)| *multiplier)
+        .unwrap_or(1);
+    steps * multiplier
+}
+
+/// Detents passed between two readings of the wrapping counter of the `detents` size.
+/// Zero means no movement or an overspeed, when more than `max_steps` detents were passed.
+pub fn get_steps(down: bool, prev: u16, current: u16, detents: u16, max_steps: u16) -> u16 {
     if prev == current {
         return 0; //No changes
     }
-    let steps = if direction { //Counting backwards
+    let steps = if down { //Counting backwards
         if current > prev { //On backward count this means wrap
-            512 - current + prev
+            detents - current + prev
         } else {
             prev - current
         }
+    } else if current < prev { //On forward count this means wrap
+        detents - prev + current
     } else {
-        if current < prev { //On forward count this means wrap
-            512 - prev + current
-        } else {
-            current - prev
-        }
+        current - prev
     };
-    if steps > 50 { //Most probably overspeed error, ignore it
+    if steps > max_steps { //Most probably overspeed error, ignore it
         0
     } else {
         steps
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DETENTS: u16 = 512;
+    const MAX_STEPS: u16 = 50;
+
+    /**
+    Timer with the counter set by the test.
+     */
+    struct MockTimer {
+        count: u16,
+        down: bool
+    }
+
+    impl EncoderTimer for MockTimer {
+        fn configure(&mut self, config: &EncoderConfig) {
+            self.count = config.counts_per_detent * config.detents / 2;
+        }
+
+        fn count(&self) -> u16 {
+            self.count
+        }
+
+        fn is_counting_down(&self) -> bool {
+            self.down
+        }
+    }
+
+    #[test]
+    fn steps_without_wrap() {
+        assert_eq!(get_steps(false, 256, 256, DETENTS, MAX_STEPS), 0);
+        assert_eq!(get_steps(false, 256, 259, DETENTS, MAX_STEPS), 3);
+        assert_eq!(get_steps(true, 256, 250, DETENTS, MAX_STEPS), 6);
+    }
+
+    #[test]
+    fn steps_up_across_top() {
+        assert_eq!(get_steps(false, DETENTS - 2, 1, DETENTS, MAX_STEPS), 3);
+        assert_eq!(get_steps(false, DETENTS - 1, 0, DETENTS, MAX_STEPS), 1);
+    }
+
+    #[test]
+    fn steps_down_across_zero() {
+        assert_eq!(get_steps(true, 1, DETENTS - 2, DETENTS, MAX_STEPS), 3);
+        assert_eq!(get_steps(true, 0, DETENTS - 1, DETENTS, MAX_STEPS), 1);
+    }
+
+    #[test]
+    fn overspeed_is_ignored() {
+        assert_eq!(get_steps(false, 100, 100 + MAX_STEPS, DETENTS, MAX_STEPS), MAX_STEPS);
+        assert_eq!(get_steps(false, 100, 101 + MAX_STEPS, DETENTS, MAX_STEPS), 0);
+        assert_eq!(get_steps(true, 100, 99 - MAX_STEPS, DETENTS, MAX_STEPS), 0);
+        assert_eq!(get_steps(false, DETENTS - 10, MAX_STEPS, DETENTS, MAX_STEPS), 0); //Across the wrap too
+    }
+
+    #[test]
+    fn counts_are_divided_into_detents() {
+        let config = EncoderConfig::default();
+        let mut reader = EncoderReader::new(MockTimer { count: 0, down: false }, config, 0, 1000);
+        let start = config.counts_per_detent * config.detents / 2;
+        reader.enc.count = start + 1; //Half a detent isn't a step
+        assert_eq!(reader.read(100), None);
+        reader.enc.count = start + 2;
+        assert_eq!(reader.read(100), Some(101));
+        reader.enc.count = start + 7;
+        assert_eq!(reader.read(100), Some(102));
+        reader.enc.down = true;
+        reader.enc.count = start + 2;
+        assert_eq!(reader.read_delta(), -2);
+    }
+
+    #[test]
+    fn reading_is_kept_in_margins() {
+        let mut reader = EncoderReader::new(MockTimer { count: 0, down: true }, EncoderConfig::default(), 10, 20);
+        reader.enc.count -= 10;
+        assert_eq!(reader.read(12), Some(10));
+    }
+}
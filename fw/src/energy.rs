@@ -0,0 +1,38 @@
+use crate::settings::Settings;
+
+const MS_PER_HOUR: u64 = 3_600_000;
+
+/**
+Heater and motor on-time of a cooking session, turned to the energy by the rated powers from the settings.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Energy {
+    heater_ms: u32,
+    motor_ms: u32
+}
+
+impl Energy {
+    pub fn add(&mut self, passed: u32, heater: bool, motor: bool) {
+        if heater {
+            self.heater_ms = self.heater_ms.saturating_add(passed);
+        }
+        if motor {
+            self.motor_ms = self.motor_ms.saturating_add(passed);
+        }
+    }
+
+    pub fn watt_hours(&self, settings: &Settings) -> u32 {
+        let watt_ms = self.heater_ms as u64 * settings.heater_power as u64 + self.motor_ms as u64 * settings.motor_power as u64;
+        (watt_ms / MS_PER_HOUR) as u32
+    }
+
+    /// Cost in the hundredths of the currency
+    pub fn cost(&self, settings: &Settings) -> u32 {
+        cost(self.watt_hours(settings), settings)
+    }
+}
+
+/// Cost of the energy in the hundredths of the currency
+pub fn cost(watt_hours: u32, settings: &Settings) -> u32 {
+    (watt_hours as u64 * settings.energy_price as u64 / 1000) as u32
+}
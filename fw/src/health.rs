@@ -0,0 +1,49 @@
+const START_MAX: u16 = 60; //Degrees of Celsius, heat-up is only measured from the cold oven
+const RISE: u16 = 50; //Degrees of Celsius, measured temperature rise
+const MIN_DUTY: u32 = 90; //Percents of the heater on-time for the measurement to be valid
+const MINUTE_IN_MS: u32 = 60_000;
+
+pub enum HeatUp {
+    Measuring,
+    /// Tenths of degree of Celsius per minute
+    Rate(u16),
+    /// Heater didn't run at the full power
+    Invalid
+}
+
+/**
+Measures the heat-up rate from the cold oven, while the heater runs at the full duty.
+
+Measurement is invalid if the heater duty drops below the limit, as the rate is only comparable at the full power.
+ */
+pub struct HeatUpMeter {
+    start: u16,
+    passed: u32,
+    heater: u32
+}
+
+impl HeatUpMeter {
+    /// Starts the measurement, if the oven is cold enough
+    pub fn new(temp: u16, temp_requested: u16) -> Option<Self> {
+        if temp <= START_MAX && temp_requested > temp + RISE {
+            Some(HeatUpMeter { start: temp, passed: 0, heater: 0 })
+        } else {
+            None
+        }
+    }
+
+    /// Accounts the time passed, reports the rate once the temperature has risen enough
+    pub fn add(&mut self, passed: u32, heater: bool, temp: u16) -> HeatUp {
+        self.passed += passed;
+        if heater {
+            self.heater += passed;
+        }
+        if temp < self.start + RISE || self.passed == 0 {
+            HeatUp::Measuring
+        } else if self.heater * 100 / self.passed >= MIN_DUTY {
+            HeatUp::Rate((RISE as u32 * 10 * MINUTE_IN_MS / self.passed) as u16)
+        } else {
+            HeatUp::Invalid
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use stm32f3xx_hal::hal::blocking::delay::{DelayMs, DelayUs};
+use stm32f3xx_hal::hal::digital::v2::OutputPin;
+
+const CLEAR: u8 = 0b0000_0001;
+const ENTRY_MODE: u8 = 0b0000_0110; //Increment address, no display shift
+const DISPLAY_ON: u8 = 0b0000_1100; //Display on, cursor and blinking off
+const DISPLAY_OFF: u8 = 0b0000_1000;
+const FUNCTION_SET: u8 = 0b0010_1000; //4 bit bus, 2 lines, 5x8 font
+const SET_CGRAM: u8 = 0b0100_0000;
+const SET_DDRAM: u8 = 0b1000_0000;
+
+//HD44780 timings, with a margin for the 3.3V supply
+const POWER_ON_MS: u8 = 50; //40ms after Vcc rises to 2.7V
+const ENABLE_PULSE_US: u16 = 1; //450ns enable high and 1us enable cycle
+const EXECUTION_US: u16 = 50; //37us for most of the instructions
+const CLEAR_US: u16 = 2000; //1.52ms for clear and return home
+
+/**
+4 bit HD44780 bus.
+ */
+pub struct FourBitBus<RS, EN, D4, D5, D6, D7> {
+    rs: RS,
+    en: EN,
+    d4: D4,
+    d5: D5,
+    d6: D6,
+    d7: D7
+}
+
+impl<RS: OutputPin, EN: OutputPin, D4: OutputPin, D5: OutputPin, D6: OutputPin, D7: OutputPin> FourBitBus<RS, EN, D4, D5, D6, D7> {
+    pub fn from_pins(rs: RS, en: EN, d4: D4, d5: D5, d6: D6, d7: D7) -> Self {
+        FourBitBus { rs, en, d4, d5, d6, d7 }
+    }
+
+    /// Latches the lower 4 bits of the `nibble`
+    fn write_nibble<D: DelayUs<u16>>(&mut self, nibble: u8, data: bool, delay: &mut D) {
+        self.rs.set_state(data.into()).ok();
+        self.d4.set_state((nibble & 0b0001 != 0).into()).ok();
+        self.d5.set_state((nibble & 0b0010 != 0).into()).ok();
+        self.d6.set_state((nibble & 0b0100 != 0).into()).ok();
+        self.d7.set_state((nibble & 0b1000 != 0).into()).ok();
+        self.en.set_high().ok();
+        delay.delay_us(ENABLE_PULSE_US);
+        self.en.set_low().ok(); //Data is latched on the falling edge
+        delay.delay_us(ENABLE_PULSE_US);
+    }
+
+    /// Writes the byte and waits for the instruction to execute
+    fn write<D: DelayUs<u16>>(&mut self, byte: u8, data: bool, delay: &mut D) {
+        self.write_nibble(byte >> 4, data, delay);
+        self.write_nibble(byte & 0b1111, data, delay);
+        delay.delay_us(EXECUTION_US);
+    }
+}
+
+/**
+HD44780 controller on the 4 bit bus. Busy flag is not readable on this board, so every operation
+waits for the datasheet execution time.
+ */
+pub struct Hd44780<RS, EN, D4, D5, D6, D7> {
+    bus: FourBitBus<RS, EN, D4, D5, D6, D7>
+}
+
+impl<RS: OutputPin, EN: OutputPin, D4: OutputPin, D5: OutputPin, D6: OutputPin, D7: OutputPin> Hd44780<RS, EN, D4, D5, D6, D7> {
+    pub fn new_4bit<D: DelayUs<u16> + DelayMs<u8>>(bus: FourBitBus<RS, EN, D4, D5, D6, D7>, delay: &mut D) -> Self {
+        let mut lcd = Hd44780 { bus };
+        //Initialization by instruction, as the power on reset may fail on the slow rising supply
+        delay.delay_ms(POWER_ON_MS);
+        lcd.bus.write_nibble(0b0011, false, delay);
+        delay.delay_ms(5u8); //4.1ms
+        lcd.bus.write_nibble(0b0011, false, delay);
+        delay.delay_us(150); //100us
+        lcd.bus.write_nibble(0b0011, false, delay);
+        delay.delay_us(EXECUTION_US);
+        lcd.bus.write_nibble(0b0010, false, delay); //Switch to 4 bit mode
+        delay.delay_us(EXECUTION_US);
+        lcd.bus.write(FUNCTION_SET, false, delay);
+        lcd.bus.write(DISPLAY_ON, false, delay);
+        lcd.bus.write(ENTRY_MODE, false, delay);
+        lcd.clear(delay);
+        lcd
+    }
+
+    pub fn clear<D: DelayUs<u16>>(&mut self, delay: &mut D) {
+        self.bus.write(CLEAR, false, delay);
+        delay.delay_us(CLEAR_US);
+    }
+
+    /// Switches the display on or off, keeping its content
+    pub fn set_display<D: DelayUs<u16>>(&mut self, on: bool, delay: &mut D) {
+        self.bus.write(if on { DISPLAY_ON } else { DISPLAY_OFF }, false, delay);
+    }
+
+    /// Moves the cursor to the DDRAM position, second line starts at 40
+    pub fn set_cursor_pos<D: DelayUs<u16>>(&mut self, position: u8, delay: &mut D) {
+        self.bus.write(SET_DDRAM | (position & 0b0111_1111), false, delay);
+    }
+
+    /// Loads 5x8 pattern of the custom character `slot` (0-7) to the CGRAM.
+    /// Cursor is left in the CGRAM, so it should be positioned before writing the text.
+    pub fn set_glyph<D: DelayUs<u16>>(&mut self, slot: u8, pattern: &[u8; 8], delay: &mut D) {
+        self.bus.write(SET_CGRAM | ((slot & 0b0111) << 3), false, delay);
+        pattern.iter().for_each(|row| self.write_byte(*row, delay));
+    }
+
+    pub fn write_byte<D: DelayUs<u16>>(&mut self, data: u8, delay: &mut D) {
+        self.bus.write(data, true, delay);
+    }
+}
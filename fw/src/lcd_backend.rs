@@ -0,0 +1,86 @@
+use crate::board;
+use crate::delay::DwtDelay;
+use crate::display::{DisplayBackend, Screen, GLYPH_SLOTS, LINES, WIDTH};
+
+const LINE_ADDRESS: u8 = 40; //DDRAM address of the second line
+
+/**
+HD44780 display, connected to the board.
+ */
+pub struct Hd44780Backend {
+    lcd: board::LCD,
+    delay: DwtDelay
+}
+
+impl Hd44780Backend {
+    pub fn new(lcd: board::LCD, delay: DwtDelay) -> Self {
+        Hd44780Backend { lcd, delay }
+    }
+}
+
+impl DisplayBackend for Hd44780Backend {
+    fn clear(&mut self) {
+        self.lcd.clear(&mut self.delay);
+    }
+
+    fn set_cursor(&mut self, row: u8, column: u8) {
+        self.lcd.set_cursor_pos(row * LINE_ADDRESS + column, &mut self.delay);
+    }
+
+    fn write_byte(&mut self, data: u8) {
+        self.lcd.write_byte(data, &mut self.delay);
+    }
+
+    fn set_glyph(&mut self, slot: u8, pattern: &[u8; 8]) {
+        self.lcd.set_glyph(slot, pattern, &mut self.delay);
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.lcd.set_display(enabled, &mut self.delay);
+    }
+}
+
+/**
+Mirrors the screen to the HD44780, writing only the changed characters, glyphs and display state.
+It is slow, so it runs outside of the state machine.
+ */
+pub struct LcdRefresher {
+    lcd: Hd44780Backend,
+    shown: Screen
+}
+
+impl LcdRefresher {
+    pub fn new(mut lcd: Hd44780Backend) -> Self {
+        lcd.clear();
+        LcdRefresher { lcd, shown: Screen::default() }
+    }
+
+    pub fn refresh(&mut self, screen: &Screen) {
+        if self.shown.is_enabled() != screen.is_enabled() {
+            self.lcd.set_enabled(screen.is_enabled());
+            self.shown.set_enabled(screen.is_enabled());
+        }
+        for slot in 0..GLYPH_SLOTS as u8 {
+            let pattern = screen.glyph(slot);
+            if self.shown.glyph(slot) != pattern {
+                self.lcd.set_glyph(slot, pattern);
+                self.shown.set_glyph(slot, pattern);
+            }
+        }
+        let mut cursor = None;
+        for row in 0..LINES {
+            for column in 0..WIDTH {
+                let data = screen.line(row)[column];
+                if self.shown.line(row)[column] != data {
+                    if cursor != Some((row, column)) {
+                        self.lcd.set_cursor(row as u8, column as u8);
+                    }
+                    self.lcd.write_byte(data);
+                    self.shown.set_cursor(row as u8, column as u8);
+                    self.shown.write_byte(data);
+                    cursor = Some((row, column + 1));
+                }
+            }
+        }
+    }
+}
@@ -1,19 +1,37 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 //use core::sync::atomic::AtomicUsize;
 //use core::sync::atomic::Ordering;
+#[cfg(not(test))]
 use defmt_rtt as _;
 
 //use panic_probe as _;
 
+#[cfg(not(test))] //Hardware modules are left out of the host tests
 pub mod board;
+pub mod button;
 pub mod encoder;
+#[cfg(not(test))]
 pub mod delay;
 pub mod display;
+#[cfg(not(test))]
+pub mod lcd_backend;
+#[cfg(not(test))]
+pub mod lcd;
 pub mod temp_sensor;
 pub mod state;
 pub mod buzzer;
 pub mod current_sensor;
+#[cfg(not(test))]
+pub mod storage;
+pub mod settings;
+pub mod energy;
+pub mod statistics;
+pub mod health;
+#[cfg(not(test))]
+pub mod panic;
+pub mod reset;
+pub mod recovery;
 
 //#[defmt::panic_handler]
 /*fn panic() -> ! {
@@ -0,0 +1,196 @@
+use core::convert::Infallible;
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+use stm32f3xx_hal::hal::blocking::delay::{DelayMs, DelayUs};
+use stm32f3xx_hal::hal::digital::v2::OutputPin;
+use stm32f3xx_hal::pac::{GPIOA, GPIOB};
+use heapless::String;
+use crate::lcd::{FourBitBus, Hd44780};
+use crate::state::notice::Page;
+
+const RECORD_MAGIC: u32 = 0x5041_4E43; //"PANC"
+const FILE_LENGTH: usize = 24; //Tail of the source path is kept
+const MESSAGE_LENGTH: usize = 48;
+const CYCLES_PER_US: u32 = 64; //Delays are only longer if the clock isn't switched to 64MHz yet
+const LINE_ADDRESS: u8 = 40; //DDRAM address of the second line
+const SHOW_MS: u32 = 5000; //Panic code is shown before the reset
+
+/**
+Panic location and message, kept in the RAM over the reset.
+ */
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PanicRecord {
+    magic: u32,
+    pub line: u32,
+    file: [u8; FILE_LENGTH],
+    message: [u8; MESSAGE_LENGTH],
+    check: u32 //Complement of the magic and the line, as the RAM content is random after the power on
+}
+
+#[link_section = ".uninit.PANIC_RECORD"] //Not zeroed by the runtime
+static mut RECORD: MaybeUninit<PanicRecord> = MaybeUninit::uninit();
+
+fn text(bytes: &[u8]) -> &str {
+    let length = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..length]).unwrap_or("")
+}
+
+impl PanicRecord {
+    fn is_valid(&self) -> bool {
+        self.magic == RECORD_MAGIC && self.check == !(self.magic ^ self.line)
+    }
+
+    /// Source file name, without the path
+    pub fn file(&self) -> &str {
+        let file = text(&self.file);
+        file.rsplit('/').next().unwrap_or(file)
+    }
+
+    pub fn message(&self) -> &str {
+        text(&self.message)
+    }
+
+    /// Boot notice with the panic location and the beginning of the message
+    pub fn notice(&self) -> Page {
+        let mut top: String<16> = String::new();
+        let mut bottom: String<16> = String::new();
+        write!(top, "Panic {}", self.file()).unwrap_or_default();
+        write!(bottom, "L{} ", self.line).unwrap_or_default();
+        self.message().chars().take(bottom.capacity() - bottom.len()).for_each(|c| bottom.push(c).unwrap_or_default());
+        (top, bottom)
+    }
+}
+
+/// Returns the record of the panic, that caused the last reset, and clears it
+pub fn take_record() -> Option<PanicRecord> {
+    unsafe {
+        let record = addr_of_mut!(RECORD) as *mut PanicRecord;
+        let stored = record.read_volatile();
+        addr_of_mut!((*record).magic).write_volatile(0);
+        stored.is_valid().then_some(stored)
+    }
+}
+
+/**
+Writer into a fixed byte buffer, the text over its capacity is dropped. Only ASCII is kept, as it goes to the LCD.
+ */
+struct BufferWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize
+}
+
+impl Write for BufferWriter<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            if self.length >= self.buffer.len() {
+                break;
+            }
+            self.buffer[self.length] = if byte.is_ascii() { byte } else { b'?' };
+            self.length += 1;
+        }
+        Ok(())
+    }
+}
+
+/**
+GPIO pin, driven by the direct register writes. Board pins are owned by the RTIC tasks, that never run again after the panic.
+ */
+#[derive(Clone, Copy)]
+enum RawPin {
+    A(u8),
+    B(u8)
+}
+
+impl RawPin {
+    fn set(&self, high: bool) {
+        let bits = |pin: u8| if high { 1u32 << pin } else { 1u32 << (pin + 16) }; //Set or reset half of BSRR
+        unsafe {
+            match *self {
+                RawPin::A(pin) => (*GPIOA::ptr()).bsrr.write(|w| w.bits(bits(pin))),
+                RawPin::B(pin) => (*GPIOB::ptr()).bsrr.write(|w| w.bits(bits(pin)))
+            }
+        }
+    }
+}
+
+impl OutputPin for RawPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true);
+        Ok(())
+    }
+}
+
+/**
+Busy wait by the instruction count, the cycle counter might be not running yet.
+ */
+struct SpinDelay;
+
+impl DelayUs<u16> for SpinDelay {
+    fn delay_us(&mut self, us: u16) {
+        cortex_m::asm::delay(us as u32 * CYCLES_PER_US);
+    }
+}
+
+impl DelayMs<u8> for SpinDelay {
+    fn delay_ms(&mut self, ms: u8) {
+        cortex_m::asm::delay(ms as u32 * 1000 * CYCLES_PER_US);
+    }
+}
+
+/// Switches the heater, motor and cook LED off, pins are the ones from the `board`
+fn shutdown() {
+    RawPin::A(12).set(false); //Heater
+    RawPin::A(11).set(true); //Motor enable is inverted
+    RawPin::A(1).set(false); //Cook LED
+}
+
+/// Shows the panic location, the display is initialized again, as the panic might have interrupted its refresh
+fn show(record: &PanicRecord) {
+    let mut delay = SpinDelay;
+    let bus = FourBitBus::from_pins(RawPin::A(15), RawPin::B(7), RawPin::B(3), RawPin::A(10), RawPin::B(0), RawPin::A(3));
+    let mut lcd = Hd44780::new_4bit(bus, &mut delay);
+    let mut top = [0u8; 16];
+    let mut writer = BufferWriter { buffer: &mut top, length: 0 };
+    write!(writer, "PANIC L{}", record.line).unwrap_or_default();
+    lcd.set_cursor_pos(0, &mut delay);
+    top.iter().take_while(|b| **b != 0).for_each(|b| lcd.write_byte(*b, &mut delay));
+    lcd.set_cursor_pos(LINE_ADDRESS, &mut delay);
+    record.file().bytes().take(16).for_each(|b| lcd.write_byte(b, &mut delay));
+}
+
+#[cfg_attr(target_os = "none", panic_handler)]
+#[cfg_attr(not(target_os = "none"), allow(dead_code))] //Hosted builds link the std, that has its own handler
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    shutdown(); //First of all, before anything else may fail
+
+    let mut record = PanicRecord { magic: RECORD_MAGIC, line: 0, file: [0; FILE_LENGTH], message: [0; MESSAGE_LENGTH], check: 0 };
+    if let Some(location) = info.location() {
+        record.line = location.line();
+        let file = location.file();
+        let tail = file.len().saturating_sub(FILE_LENGTH);
+        let mut writer = BufferWriter { buffer: &mut record.file, length: 0 };
+        writer.write_str(file.get(tail..).unwrap_or(file)).unwrap_or_default();
+    }
+    let mut writer = BufferWriter { buffer: &mut record.message, length: 0 };
+    write!(writer, "{}", info.message()).unwrap_or_default();
+    record.check = !(record.magic ^ record.line);
+    unsafe {
+        (addr_of_mut!(RECORD) as *mut PanicRecord).write_volatile(record);
+    }
+
+    show(&record);
+    cortex_m::asm::delay(SHOW_MS * 1000 * CYCLES_PER_US);
+    shutdown();
+    cortex_m::peripheral::SCB::sys_reset() //The record survives the reset, it is reported on the boot
+}
@@ -0,0 +1,75 @@
+#[cfg(not(test))]
+use crate::storage::{FlashStorage, StorageError};
+
+const CHECKPOINT_WORDS: usize = 3;
+const CHECKPOINT_MARK: u16 = 0xC100; //Upper byte of the flags word, tells the checkpoint from the blank flash
+const MANUAL: u16 = 0b01;
+const PREHEATED: u16 = 0b10;
+
+/**
+State of the running cooking session, journaled to the flash every minute,
+so the session can be resumed after the power loss.
+ */
+#[derive(Clone, Copy)]
+pub struct Checkpoint {
+    pub temp: u16, //Requested temperature, degrees of Celsius
+    pub time: u16, //Seconds left, or the elapsed ones for the manual cooking
+    pub manual: bool,
+    pub preheated: bool
+}
+
+impl Checkpoint {
+    /// Decodes the journal record, None for the blank or foreign data
+    fn from_words(words: &[u16; CHECKPOINT_WORDS]) -> Option<Self> {
+        if words[0] & 0xFF00 != CHECKPOINT_MARK {
+            return None;
+        }
+        Some(Checkpoint { temp: words[1], time: words[2], manual: words[0] & MANUAL != 0, preheated: words[0] & PREHEATED != 0 })
+    }
+
+    fn to_words(self) -> [u16; CHECKPOINT_WORDS] {
+        let flags = CHECKPOINT_MARK | if self.manual { MANUAL } else { 0 } | if self.preheated { PREHEATED } else { 0 };
+        [flags, self.temp, self.time]
+    }
+}
+
+#[cfg(not(test))]
+impl Checkpoint {
+    /// Last checkpoint of the session, interrupted by the reset
+    pub fn load(storage: &FlashStorage) -> Option<Self> {
+        Checkpoint::from_words(&storage.last::<CHECKPOINT_WORDS>()?)
+    }
+
+    /// Appends the checkpoint to the journal, the full journal is cleared and started over with it
+    pub fn save(&self, storage: &mut FlashStorage) -> Result<(), StorageError> {
+        match storage.append(&self.to_words()) {
+            Err(StorageError::Full) => storage.clear().and_then(|_| storage.append(&self.to_words())),
+            result => result
+        }
+    }
+
+    /// Session is over, there is nothing to resume
+    pub fn clear(storage: &mut FlashStorage) {
+        storage.clear().unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        for (manual, preheated) in [(false, false), (true, false), (false, true), (true, true)] {
+            let checkpoint = Checkpoint { temp: 185, time: 1234, manual, preheated };
+            let loaded = Checkpoint::from_words(&checkpoint.to_words()).expect("marked record");
+            assert_eq!((loaded.temp, loaded.time, loaded.manual, loaded.preheated), (185, 1234, manual, preheated));
+        }
+    }
+
+    #[test]
+    fn blank_flash_is_no_checkpoint() {
+        assert!(Checkpoint::from_words(&[0xFFFF; CHECKPOINT_WORDS]).is_none());
+        assert!(Checkpoint::from_words(&[0; CHECKPOINT_WORDS]).is_none());
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(not(test))]
+use stm32f3xx_hal::pac::RCC;
+
+/**
+Cause of the last reset, by the RCC flags. Brown-out can't be told from the power-on,
+as the power down reset of this MCU sets the same flag.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+    PowerOn,
+    Pin,
+    Software,
+    Watchdog,
+    WindowWatchdog,
+    LowPower,
+    OptionBytes
+}
+
+impl ResetCause {
+    /// Reads and clears the reset flags, has to be called once at the boot
+    #[cfg(not(test))]
+    pub fn take(rcc: &RCC) -> Self {
+        let csr = rcc.csr.read();
+        //Internal resets pulse the reset pin too, so its flag is the last one checked
+        let cause = if csr.iwdgrstf().bit_is_set() {
+            ResetCause::Watchdog
+        } else if csr.wwdgrstf().bit_is_set() {
+            ResetCause::WindowWatchdog
+        } else if csr.sftrstf().bit_is_set() {
+            ResetCause::Software
+        } else if csr.lpwrrstf().bit_is_set() {
+            ResetCause::LowPower
+        } else if csr.oblrstf().bit_is_set() {
+            ResetCause::OptionBytes
+        } else if csr.porrstf().bit_is_set() {
+            ResetCause::PowerOn
+        } else {
+            ResetCause::Pin
+        };
+        rcc.csr.modify(|_, w| w.rmvf().clear());
+        cause
+    }
+
+    /// Code of the cause in the statistics
+    pub fn code(&self) -> u16 {
+        *self as u16 + 1 //Zero is for no reset recorded
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        [ResetCause::PowerOn, ResetCause::Pin, ResetCause::Software, ResetCause::Watchdog, ResetCause::WindowWatchdog, ResetCause::LowPower, ResetCause::OptionBytes]
+            .into_iter().find(|cause| cause.code() == code)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "Power-on",
+            ResetCause::Pin => "Reset pin",
+            ResetCause::Software => "Software",
+            ResetCause::Watchdog => "Watchdog",
+            ResetCause::WindowWatchdog => "Window watchdog",
+            ResetCause::LowPower => "Low-power",
+            ResetCause::OptionBytes => "Option bytes"
+        }
+    }
+}
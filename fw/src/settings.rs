@@ -0,0 +1,250 @@
+use libm::roundf;
+#[cfg(not(test))]
+use crate::storage::FlashStorage;
+use crate::temp_sensor::BoardLimits;
+
+const SETTINGS_VERSION: u16 = 1; //Layout version, new fields are appended without a change
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TempUnit {
+    Celsius,
+    Fahrenheit
+}
+
+impl TempUnit {
+    /// Encoder step in the unit degrees
+    pub fn step(&self) -> u16 {
+        match self {
+            TempUnit::Celsius => 5,
+            TempUnit::Fahrenheit => 10
+        }
+    }
+
+    /// Settable temperature range in the degrees of this unit, 50-250°C or 120-480°F
+    pub fn limits(&self) -> (u16, u16) {
+        match self {
+            TempUnit::Celsius => (50, 250),
+            TempUnit::Fahrenheit => (120, 480)
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            TempUnit::Celsius => "C",
+            TempUnit::Fahrenheit => "F"
+        }
+    }
+
+    pub fn from_celsius(&self, celsius: f32) -> f32 {
+        match self {
+            TempUnit::Celsius => celsius,
+            TempUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0
+        }
+    }
+
+    pub fn to_celsius(&self, value: u16) -> u16 {
+        match self {
+            TempUnit::Celsius => value,
+            TempUnit::Fahrenheit => roundf((value as f32 - 32.0) * 5.0 / 9.0) as u16
+        }
+    }
+
+    /// Converts value to the other unit, keeping it on the encoder step grid and in the limits
+    pub fn convert(&self, value: u16, to: TempUnit) -> u16 {
+        let celsius = self.to_celsius(value);
+        let converted = roundf(to.from_celsius(celsius as f32) / to.step() as f32) as u16 * to.step();
+        let (low, high) = to.limits();
+        converted.clamp(low, high)
+    }
+}
+
+pub const PRESETS: usize = 4;
+const PRESETS_WORD: usize = 1;
+const FIELDS_WORD: usize = PRESETS_WORD + PRESETS * 2;
+const FIELDS: usize = 21;
+const SETTINGS_WORDS: usize = FIELDS_WORD + FIELDS;
+
+//Tuned with Ziegler–Nichols method, gains are stored multiplied by 1000
+const K_P: u16 = 4800; //K_u = 8, K_P = 0.6*8
+const K_I: u16 = 60; //P_u = 145seconds = 0.006Hz, K_i = 1.2*K_u/P_u=
+const K_D: u16 = 7400; //K_d=0.075*K_u*P_u
+
+/**
+Stored temperature (always in Celsius) and time pair.
+ */
+#[derive(Clone, Copy)]
+pub struct Preset {
+    pub temp: u16,
+    pub time: u16 //Minutes
+}
+
+/**
+User settings, persisted in the internal flash.
+ */
+#[derive(Clone, Copy)]
+pub struct Settings {
+    pub unit: TempUnit,
+    pub sound: bool,
+    pub temp_offset: i16, //Thermocouple calibration, degrees of Celsius
+    pub k_p: u16,
+    pub k_i: u16,
+    pub k_d: u16,
+    pub sleep_timeout: u16, //Minutes of inactivity before the display is switched off, 0 disables it
+    pub alarm_interval: u16, //Seconds between the done alarm repeats, 0 plays it once
+    pub alarm_duration: u16, //Minutes the done alarm is repeated for
+    pub auto_resume: bool, //Paused cooking is resumed on the lid closing, otherwise with the cook button
+    pub pause_timeout: u16, //Minutes the paused cooking is kept before it is abandoned
+    pub manual_limit: u16, //Minutes the cooking without the timer may run for
+    pub heater_power: u16, //Rated power in watts, for the energy accounting
+    pub motor_power: u16, //Rated power in watts
+    pub energy_price: u16, //Hundredths of the currency per kWh
+    pub board_warning: u16, //Board temperature bands, degrees of Celsius
+    pub board_derating: u16,
+    pub board_halt: u16,
+    pub cool_limit: u16, //Minutes of the fan run-on after the cooking, 0 disables the cool-down
+    pub cool_board: u16, //Cool-down ends when both the board and the oven are below these, degrees of Celsius
+    pub cool_oven: u16,
+    pub presets: [Preset; PRESETS]
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            unit: TempUnit::Celsius,
+            sound: true,
+            temp_offset: 5,
+            k_p: K_P,
+            k_i: K_I,
+            k_d: K_D,
+            sleep_timeout: 0,
+            alarm_interval: 30,
+            alarm_duration: 10,
+            auto_resume: true,
+            pause_timeout: 10,
+            manual_limit: 120,
+            heater_power: 1300,
+            motor_power: 20,
+            energy_price: 25,
+            board_warning: 50,
+            board_derating: 55,
+            board_halt: 60,
+            cool_limit: 5,
+            cool_board: 45,
+            cool_oven: 80,
+            presets: [
+                Preset { temp: 180, time: 30 },
+                Preset { temp: 200, time: 20 },
+                Preset { temp: 160, time: 45 },
+                Preset { temp: 220, time: 10 }
+            ]
+        }
+    }
+}
+
+impl Settings {
+    /// Decodes the stored record, the fields it lacks keep the defaults
+    fn from_words(stored: &[u16]) -> Option<Self> {
+        if stored.first() != Some(&SETTINGS_VERSION) || !(FIELDS_WORD..=SETTINGS_WORDS).contains(&stored.len()) {
+            return None;
+        }
+        let mut words = Settings::default().to_words();
+        words[..stored.len()].copy_from_slice(stored);
+        let mut presets = [Preset { temp: 0, time: 0 }; PRESETS];
+        for (index, preset) in presets.iter_mut().enumerate() {
+            *preset = Preset { temp: words[PRESETS_WORD + index * 2], time: words[PRESETS_WORD + 1 + index * 2] };
+        }
+        let fields = &words[FIELDS_WORD..];
+        Some(Settings {
+            unit: if fields[0] == 1 { TempUnit::Fahrenheit } else { TempUnit::Celsius },
+            sound: fields[1] != 0,
+            temp_offset: fields[2] as i16,
+            k_p: fields[3],
+            k_i: fields[4],
+            k_d: fields[5],
+            sleep_timeout: fields[6],
+            alarm_interval: fields[7],
+            alarm_duration: fields[8],
+            auto_resume: fields[9] != 0,
+            pause_timeout: fields[10],
+            manual_limit: fields[11],
+            heater_power: fields[12],
+            motor_power: fields[13],
+            energy_price: fields[14],
+            board_warning: fields[15],
+            board_derating: fields[16],
+            board_halt: fields[17],
+            cool_limit: fields[18],
+            cool_board: fields[19],
+            cool_oven: fields[20],
+            presets
+        })
+    }
+
+    /// Presets go first, so the new fields are only appended to the record
+    fn to_words(self) -> [u16; SETTINGS_WORDS] {
+        let unit = match self.unit {
+            TempUnit::Celsius => 0,
+            TempUnit::Fahrenheit => 1
+        };
+        let mut words = [0u16; SETTINGS_WORDS];
+        words[0] = SETTINGS_VERSION;
+        for (index, preset) in self.presets.iter().enumerate() {
+            words[PRESETS_WORD + index * 2] = preset.temp;
+            words[PRESETS_WORD + 1 + index * 2] = preset.time;
+        }
+        words[FIELDS_WORD..].copy_from_slice(&[unit, self.sound as u16, self.temp_offset as u16, self.k_p, self.k_i, self.k_d, self.sleep_timeout,
+            self.alarm_interval, self.alarm_duration, self.auto_resume as u16, self.pause_timeout, self.manual_limit, self.heater_power, self.motor_power, self.energy_price,
+            self.board_warning, self.board_derating, self.board_halt, self.cool_limit, self.cool_board, self.cool_oven]);
+        words
+    }
+
+    pub fn board_limits(&self) -> BoardLimits {
+        BoardLimits { warning: self.board_warning, derating: self.board_derating, halt: self.board_halt }
+    }
+
+    pub fn gains(&self) -> (f32, f32, f32) {
+        (self.k_p as f32 / 1000.0, self.k_i as f32 / 1000.0, self.k_d as f32 / 1000.0)
+    }
+}
+
+#[cfg(not(test))]
+impl Settings {
+    pub fn load(storage: &FlashStorage) -> Self {
+        storage.load_up_to::<SETTINGS_WORDS>().and_then(|(words, length)| Settings::from_words(&words[..length])).unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut FlashStorage) {
+        storage.store(&self.to_words()).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let settings = Settings { unit: TempUnit::Fahrenheit, sound: false, temp_offset: -7, cool_limit: 0, presets: [Preset { temp: 90, time: 120 }; PRESETS], ..Settings::default() };
+        let loaded = Settings::from_words(&settings.to_words()).expect("record of the current version");
+        assert!(loaded.unit == TempUnit::Fahrenheit && !loaded.sound);
+        assert_eq!((loaded.temp_offset, loaded.cool_limit, loaded.presets[3].temp, loaded.presets[3].time), (-7, 0, 90, 120));
+        assert_eq!(loaded.to_words(), settings.to_words());
+    }
+
+    #[test]
+    fn shorter_record_keeps_defaults() {
+        let settings = Settings { sound: false, sleep_timeout: 3, alarm_interval: 0, cool_limit: 0, presets: [Preset { temp: 90, time: 120 }; PRESETS], ..Settings::default() };
+        let loaded = Settings::from_words(&settings.to_words()[..FIELDS_WORD + 7]).expect("record of the current layout");
+        assert!(!loaded.sound);
+        assert_eq!((loaded.sleep_timeout, loaded.presets[0].temp, loaded.presets[0].time), (3, 90, 120));
+        assert_eq!((loaded.alarm_interval, loaded.cool_limit), (30, 5), "fields, missing in the record, are the default ones");
+    }
+
+    #[test]
+    fn other_version_is_ignored() {
+        let mut words = Settings::default().to_words();
+        assert!(Settings::from_words(&words[..FIELDS_WORD - 1]).is_none(), "presets are cut off");
+        words[0] = SETTINGS_VERSION + 1;
+        assert!(Settings::from_words(&words).is_none());
+    }
+}
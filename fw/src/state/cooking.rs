@@ -1,108 +1,235 @@
 use pid::Pid;
-use stm32f3xx_hal::prelude::_embedded_hal_digital_OutputPin;
-use crate::state::{Oven, OvenControl, OvenControlHardware};
-use crate::state::lid::LidOpen;
-use crate::state::ready::OvenReady;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+use crate::state::done::OvenDone;
+use crate::state::paused::OvenPaused;
 use libm::roundf;
-use crate::current_sensor::CurrentSensor;
+use crate::buzzer::Sound;
+use crate::display::{Glyph, StatusIcons};
+use crate::energy::Energy;
+use crate::health::{HeatUp, HeatUpMeter};
+use crate::recovery::Checkpoint;
 use crate::state::OvenHalt;
-use crate::temp_sensor::TempSensor;
+use crate::temp_sensor::BoardHeat;
 
-const MINUTE_IN_MS: u16 = 600; //State update timer runs in 100ms=0.1s ticks, thus minute is a 600 ticks
-const K_P: f32 = 4.8; //K_u = 8, K_P = 0.6*8
-const K_I: f32 = 0.06; //P_u = 145seconds = 0.006Hz, K_i = 1.2*K_u/P_u=
-const K_D: f32 = 7.4; //K_d=0.075*K_u*P_u
+const SECOND_IN_MS: u32 = 1000;
+const PRE_DONE: u16 = 60; //Seconds before the end for the pre-done sound
+const PREHEAT_MARGIN: u16 = 5; //Preheating is over when temperature comes that close to the setpoint
+const CHECKPOINT_TICKS: u32 = 600; //Session is checkpointed every minute
 
 pub struct Cooking {
-    hw: OvenControlHardware,
     heater_percents: u8,
     heater_updates: u16,
-    minute_delay: u16,
+    remaining: u32, //Milliseconds of the cooking left
+    last_tick: Option<u32>, //Monotonic time of the previous tick, none after start or resume
     temp_actual: u16,
     temp_intenal: u16,
-    pid: Pid<f32>
+    pid: Pid<f32>,
+    elapsed: u32, //Milliseconds
+    preheated: bool,
+    energy: Energy,
+    heat_up: Option<HeatUpMeter>, //Heat-up rate measurement, only from the cold start
+    board: BoardHeat,
+    duty_limit: u8, //Percents, heater is derated when the board is too hot
+    ticks: u32
 }
 
 impl Cooking {
-    pub fn new(mut hw: OvenControlHardware) -> Self {
+    pub fn new(ctx: &mut Context) -> Self {
         let mut pid = Pid::new(50.0, 150.0);
-        //defmt::println!("K_P: {}, K_I: {}, K_D: {}", K_P, K_I, K_D);
-        pid.p(K_P, 150.0);
-        pid.i(K_I, 150.0);
-        pid.d(K_D, 150.0);
+        let (k_p, k_i, k_d) = ctx.settings.gains();
+        //defmt::println!("K_P: {}, K_I: {}, K_D: {}", k_p, k_i, k_d);
+        pid.p(k_p, 150.0);
+        pid.i(k_i, 150.0);
+        pid.d(k_d, 150.0);
 
-        hw.display.message("*****Cooking****");
-        hw.motor.set_low().unwrap_or_default(); //Immediately start motor on cooking start
-        hw.cook_ld.set_high().unwrap_or_default();
-        hw.buzzer.run_beep();
+        ctx.act(Action::Progress(0, 1));
+        ctx.act(Action::Icons(StatusIcons { heater: false, fan: true, mode: Some(Glyph::Preheating) }));
+        ctx.act(Action::Motor(true)); //Immediately start motor on cooking start
+        ctx.act(Action::CookLed(true));
+        ctx.act(Action::Sound(Sound::Start));
 
-        Cooking { hw, heater_percents: 0, heater_updates: 0, minute_delay: MINUTE_IN_MS, temp_actual: 0, temp_intenal: 0, pid}
+        Cooking { heater_percents: 0, heater_updates: 0, remaining: 0, last_tick: None, temp_actual: 0, temp_intenal: 0, pid, elapsed: 0, preheated: false, energy: Energy::default(), heat_up: None, board: BoardHeat::Normal, duty_limit: 100, ticks: 0}
     }
 
-    fn shutdown(&mut self) {
-        self.hw.cook_ld.set_low().unwrap_or_default();
-        self.hw.heater.set_low().unwrap_or_default();
-        self.hw.motor.set_high().unwrap_or_default();
+    /// Continues the session, interrupted by the power loss. Timer and temperature are restored by the manager.
+    pub fn restore(checkpoint: &Checkpoint, ctx: &mut Context) -> Self {
+        let mut cooking = Cooking::new(ctx);
+        cooking.preheated = checkpoint.preheated;
+        if checkpoint.manual {
+            cooking.elapsed = checkpoint.time as u32 * SECOND_IN_MS;
+        }
+        cooking
+    }
+
+    /// Stops the outputs and reports the session energy
+    fn finish(&self, ctx: &mut Context) {
+        ctx.shutdown();
+        ctx.act(Action::Session(self.energy));
+    }
+
+    /// Reports the heat-up rate once measured, warns if the heater is weaker than it used to be
+    fn measure_heat_up(&mut self, passed: u32, heater_on: bool, temp: u16, ctx: &mut Context) {
+        let Some(meter) = &mut self.heat_up else {
+            return;
+        };
+        match meter.add(passed, heater_on, temp) {
+            HeatUp::Measuring => return,
+            HeatUp::Rate(rate) => {
+                if ctx.statistics.is_rate_low(rate) {
+                    ctx.act(Action::Sound(Sound::Warning));
+                }
+                ctx.act(Action::HeatUp(rate));
+            },
+            HeatUp::Invalid => {}
+        }
+        self.heat_up = None;
+    }
+
+    /// Status icon of the cooking stage, board temperature warning takes precedence
+    fn mode(&self) -> Glyph {
+        if self.board >= BoardHeat::Warning {
+            Glyph::BoardHot
+        } else if self.preheated {
+            Glyph::KeepWarm
+        } else {
+            Glyph::Preheating
+        }
+    }
+
+    /// Stops the heater at the end of the cooking, the fan keeps running for the cool-down, if it is enabled.
+    /// Session is accounted by the cool-down then, once the fan stops.
+    fn complete(&self, ctx: &mut Context) {
+        if ctx.settings.cool_limit > 0 {
+            ctx.act(Action::CookLed(false));
+            ctx.act(Action::Heater(false));
+            ctx.act(Action::ClearCheckpoint);
+        } else {
+            self.finish(ctx);
+        }
+    }
+
+    pub fn energy(&self) -> Energy {
+        self.energy
+    }
+
+    /// Stops the outputs, keeping the cooking session for the resume
+    fn pause(mut self, ctx: &mut Context) -> Oven {
+        ctx.shutdown();
+        self.heater_percents = 0;
+        self.heat_up = None; //Rate isn't comparable after the pause
+        Oven::from(OvenPaused::new(self, ctx))
+    }
+
+    pub fn resume(mut self, ctx: &mut Context) -> Self {
+        self.last_tick = None; //Pause isn't counted
+        ctx.act(Action::Icons(StatusIcons { heater: false, fan: true, mode: Some(self.mode()) }));
+        ctx.act(Action::Motor(true));
+        ctx.act(Action::CookLed(true));
+        ctx.act(Action::Sound(Sound::Start));
+        self
     }
 }
 
 impl OvenControl for Cooking {
-    fn on_cook_btn(mut self) -> Oven {
-        self.shutdown();
-        Oven::from(OvenReady::new(self.hw))
+    /// Stopped by the user, the summary is shown and the oven cools down the same way as after the done alarm
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        self.complete(ctx);
+        Oven::from(OvenDone::stopped(self.energy, ctx.settings.cool_limit > 0, ctx))
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        self.on_cook_btn(ctx)
     }
 
-    fn on_sensors(mut self, lid: bool, temp_sensor: &TempSensor, current_sensor: &CurrentSensor) -> Oven {
-        self.temp_intenal = temp_sensor.get_internal_temperature().unwrap_or(0.0) as u16;
-        if temp_sensor.is_error() {
-            OvenHalt::temp_error(self.hw)
-        } else if temp_sensor.is_overheating() {
-            OvenHalt::overheating(self.hw)
-        } else if current_sensor.is_error() {
-            OvenHalt::current_error(self.hw)
-        } else if !current_sensor.is_running() {
-            OvenHalt::motor_failed(self.hw)
-        } else if current_sensor.is_overloaded() {
-            OvenHalt::motor_overload(self.hw)
-        } else if !lid {
-            self.shutdown();
-            Oven::from(LidOpen::new(self.hw))
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        self.temp_intenal = sensors.internal as u16;
+        if sensors.board > self.board && sensors.board != BoardHeat::Overheat { //Beeps once per entered band
+            ctx.act(Action::Sound(Sound::Warning));
+        }
+        self.board = sensors.board;
+        self.duty_limit = sensors.duty_limit;
+        self.heater_percents = self.heater_percents.min(self.duty_limit); //Derating applies to the running PID period too
+        let energy = self.energy;
+        let next = if let Some(halt) = OvenHalt::check_running(sensors, ctx) {
+            halt
+        } else if !sensors.lid {
+            self.pause(ctx)
         } else {
             Oven::from(self)
+        };
+        if matches!(next, Oven::OvenHalt(_)) {
+            ctx.act(Action::Session(energy));
         }
+        next
     }
 
-    fn on_settings(mut self, temp_actual: u16, temp_requested: u16, time: u16) -> (Oven, u16) {
-        self.temp_actual = temp_actual; //Saved for a PID call. It'll be outdated, but heating machines have huge inertia
+    fn on_tick(mut self, tick: &Tick, ctx: &mut Context) -> Oven {
+        self.temp_actual = tick.temp_actual; //Saved for a PID call. It'll be outdated, but heating machines have huge inertia
 
-        if self.pid.setpoint as u16 != temp_requested {
-            self.pid.setpoint(temp_requested as f32);
+        if self.pid.setpoint as u16 != tick.temp_requested {
+            self.pid.setpoint(tick.temp_requested as f32);
         }
         self.heater_updates += 1;
-        if self.heater_percents > 0 {
-            self.hw.heater.set_high().unwrap_or_default();
+        let heater_on = self.heater_percents > 0;
+        if heater_on {
             self.heater_percents -= 1;
-        } else {
-            self.hw.heater.set_low().unwrap_or_default();
         }
-        self.minute_delay -= 1;
-        let next_time = if self.minute_delay == 0 {
-            self.minute_delay = MINUTE_IN_MS;
-            let next = time - 1;
-            if next == 1 { //We have to check that here, as we want .pre_beep() to be called exactly ones, when we hit the last minute
-                self.hw.buzzer.pre_beep();
+        if !self.preheated && tick.temp_actual + PREHEAT_MARGIN >= tick.temp_requested {
+            self.preheated = true;
+            ctx.act(Action::Sound(Sound::StageChange));
+        }
+
+        //Time is measured by the monotonic clock, as the ticks may be delayed or come more often
+        let passed = self.last_tick.map(|last| tick.now.wrapping_sub(last)).unwrap_or(0);
+        if self.last_tick.is_none() && self.elapsed == 0 {
+            self.heat_up = HeatUpMeter::new(tick.temp_actual, tick.temp_requested);
+        }
+        self.last_tick = Some(tick.now);
+        self.energy.add(passed, heater_on, true);
+        self.measure_heat_up(passed, heater_on, tick.temp_actual, ctx);
+        if tick.manual { //No countdown, elapsed time is shown instead, up to the safety limit
+            self.elapsed += passed;
+            let limit = ctx.settings.manual_limit as u32 * 60 * SECOND_IN_MS;
+            let elapsed = (self.elapsed / SECOND_IN_MS) as u16;
+            if elapsed != tick.time {
+                ctx.act(Action::SetTime(elapsed));
             }
-            next
-        } else {
-            time
-        };
-        if next_time == 0 {
-            self.hw.buzzer.done_beep();
-            self.shutdown();
-            (Oven::from(OvenReady::new(self.hw)), next_time)
+            if self.elapsed >= limit {
+                self.complete(ctx);
+                return Oven::from(OvenDone::limit_reached(self.energy, ctx));
+            }
+            ctx.act(Action::Progress(self.elapsed, limit));
         } else {
-            (Oven::from(self), next_time)
+            if self.remaining == 0 {
+                self.remaining = tick.time as u32 * SECOND_IN_MS;
+            } else if tick.time > 0 { //Timer may be changed by the user, only the current second part is kept
+                let second_part = (self.remaining - 1) % SECOND_IN_MS + 1;
+                self.remaining = (tick.time as u32 - 1) * SECOND_IN_MS + second_part;
+            }
+            let passed = passed.min(self.remaining);
+            self.remaining -= passed;
+            self.elapsed += passed;
+            let next_time = if tick.time == 0 { 0 } else { self.remaining.div_ceil(SECOND_IN_MS) as u16 };
+            if next_time != tick.time {
+                if next_time <= PRE_DONE && tick.time > PRE_DONE { //Played exactly once, when we hit the last minute
+                    ctx.act(Action::Sound(Sound::PreDone));
+                }
+                ctx.act(Action::SetTime(next_time));
+            }
+            if next_time == 0 {
+                self.complete(ctx);
+                return Oven::from(OvenDone::new(self.energy, ctx));
+            }
+            ctx.act(Action::Progress(self.elapsed, self.elapsed + self.remaining));
         }
+        if self.ticks.is_multiple_of(CHECKPOINT_TICKS) {
+            let time = if tick.manual { self.elapsed / SECOND_IN_MS } else { self.remaining.div_ceil(SECOND_IN_MS) };
+            ctx.act(Action::Checkpoint(Checkpoint { temp: tick.temp_requested, time: time as u16, manual: tick.manual, preheated: self.preheated }));
+        }
+        self.ticks += 1;
+        ctx.act(Action::Heater(heater_on)); //Only while the cooking goes on, the completion switches it off
+        ctx.act(Action::Icons(StatusIcons { heater: heater_on, fan: true, mode: Some(self.mode()) }));
+        Oven::from(self)
     }
 
     fn on_pid(&mut self) {
@@ -110,13 +237,9 @@ impl OvenControl for Cooking {
         if control.output <= 0.0 || (self.temp_actual as f32 - self.pid.setpoint) > 30.0 {
             self.heater_percents = 0;
         } else {
-            self.heater_percents = roundf(control.output) as u8;
+            self.heater_percents = (roundf(control.output) as u8).min(self.duty_limit);
         }
         //defmt::println!("PID output: {}, actual_temp: {}, requested_temp: {}, intervals: {}, updates: {}, internal_temp: {}", control.output, self.temp_actual, self.pid.setpoint, self.heater_percents, self.heater_updates, self.temp_intenal);
         self.heater_updates = 0;
     }
-
-    fn get_hw_ref(&mut self) -> &mut OvenControlHardware {
-        &mut self.hw
-    }
-}
\ No newline at end of file
+}
@@ -0,0 +1,82 @@
+use crate::display::StatusIcons;
+use crate::energy::Energy;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick, TICKS_PER_MINUTE};
+use crate::state::halt::OvenHalt;
+use crate::state::lid::LidOpen;
+use crate::state::ready::OvenReady;
+
+/**
+Fan run-on after the cooking, heater is off. Cools the control board in the lid down,
+until both the board and the oven temperatures fall below the configured thresholds
+or the cool-down time limit passes.
+
+Cancelled by the cook button. Lid lifting stops the fan too.
+Session is accounted once the fan stops, with its run-on time.
+
+Can set temp/time.
+Can't start cooking.
+ */
+pub struct OvenCoolDown {
+    ticks: u32,
+    board: i16, //Degrees of Celsius
+    energy: Energy, //Of the cooking session
+    last_tick: Option<u32> //Monotonic time of the previous tick
+}
+
+impl OvenCoolDown {
+    pub fn new(energy: Energy, ctx: &mut Context) -> Self {
+        ctx.act(Action::Message("Cooling down"));
+        ctx.act(Action::Icons(StatusIcons { fan: true, ..StatusIcons::default() }));
+        OvenCoolDown{ticks: 0, board: i16::MAX, energy, last_tick: None}
+    }
+
+    /// Stops the fan and accounts the session
+    fn stop(&self, ctx: &mut Context) {
+        ctx.act(Action::Motor(false));
+        ctx.act(Action::Session(self.energy));
+    }
+
+    fn finish(self, ctx: &mut Context) -> Oven {
+        self.stop(ctx);
+        Oven::from(OvenReady::new(ctx))
+    }
+}
+
+impl OvenControl for OvenCoolDown {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        self.finish(ctx)
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        self.on_cook_btn(ctx)
+    }
+
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        self.board = sensors.internal;
+        if let Some(halt) = OvenHalt::check_running(sensors, ctx) {
+            ctx.act(Action::Session(self.energy));
+            halt
+        } else if !sensors.lid {
+            self.stop(ctx);
+            Oven::from(LidOpen::new(ctx))
+        } else {
+            Oven::from(self)
+        }
+    }
+
+    fn on_tick(mut self, tick: &Tick, ctx: &mut Context) -> Oven {
+        let settings = ctx.settings;
+        let passed = self.last_tick.map(|last| tick.now.wrapping_sub(last)).unwrap_or(0);
+        self.last_tick = Some(tick.now);
+        self.energy.add(passed, false, true);
+        self.ticks += 1;
+        let cooled = self.board < settings.cool_board as i16 && tick.temp_actual < settings.cool_oven;
+        if cooled || self.ticks >= settings.cool_limit as u32 * TICKS_PER_MINUTE {
+            self.finish(ctx)
+        } else {
+            Oven::from(self)
+        }
+    }
+
+    fn on_pid(&mut self) {}
+}
@@ -0,0 +1,129 @@
+use heapless::String;
+use core::fmt::Write;
+use crate::buzzer::Sound;
+use crate::energy::Energy;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick, TICKS_PER_MINUTE, TICKS_PER_SECOND};
+use crate::state::cool_down::OvenCoolDown;
+use crate::state::halt::OvenHalt;
+use crate::state::lid::LidOpen;
+use crate::state::ready::OvenReady;
+
+const SUMMARY_PERIOD: u32 = 30; //Message and the session energy are swapped every 3 seconds
+
+/**
+Cooking is over. Done alarm is repeated with the configured interval until it is acknowledged
+by the cook button or the lid opening, or the configured alarm duration passes.
+Cooking stopped by the user or abandoned in the pause shows the summary once, without the alarm.
+Message is alternated with the energy and cost of the session.
+If the cool-down is enabled, the fan keeps running and the cool-down follows the alarm.
+
+Can set temp/time.
+Can't start cooking.
+ */
+pub struct OvenDone {
+    ticks: u32,
+    message: &'static str,
+    energy: Energy, //Fan run-on is added while cooling
+    cooling: bool, //Fan is running, the session is accounted once it stops
+    alarm: bool, //Finished by the timer, not stopped
+    last_tick: Option<u32> //Monotonic time of the previous tick
+}
+
+impl OvenDone {
+    pub fn new(energy: Energy, ctx: &mut Context) -> Self {
+        let cooling = ctx.settings.cool_limit > 0;
+        OvenDone::with_message(energy, "    Done!    ", true, cooling, ctx)
+    }
+
+    /// Manual cooking was stopped by the safety limit
+    pub fn limit_reached(energy: Energy, ctx: &mut Context) -> Self {
+        let cooling = ctx.settings.cool_limit > 0;
+        OvenDone::with_message(energy, " Time limit! ", true, cooling, ctx)
+    }
+
+    /// Cooking was stopped by the user or abandoned, `cooling` keeps the fan running for the cool-down
+    pub fn stopped(energy: Energy, cooling: bool, ctx: &mut Context) -> Self {
+        OvenDone::with_message(energy, "   Stopped   ", false, cooling, ctx)
+    }
+
+    fn with_message(energy: Energy, message: &'static str, alarm: bool, cooling: bool, ctx: &mut Context) -> Self {
+        ctx.act(Action::Message(message));
+        if alarm {
+            ctx.act(Action::Sound(Sound::Done));
+        }
+        OvenDone{ticks: 0, message, energy, cooling, alarm, last_tick: None}
+    }
+
+    fn show_summary(&self, ctx: &mut Context) {
+        let watt_hours = self.energy.watt_hours(ctx.settings);
+        let cost = self.energy.cost(ctx.settings);
+        let mut text: String<16> = String::new();
+        write!(text, "{}.{:02}kWh {}.{:02}", watt_hours / 1000, watt_hours % 1000 / 10, cost / 100, cost % 100).unwrap_or_default();
+        ctx.act(Action::Text(text));
+    }
+
+    /// Alarm is over, the fan keeps running if the oven has to cool down
+    fn finish(self, ctx: &mut Context) -> Oven {
+        if self.cooling {
+            Oven::from(OvenCoolDown::new(self.energy, ctx))
+        } else {
+            Oven::from(OvenReady::new(ctx))
+        }
+    }
+}
+
+impl OvenControl for OvenDone {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::StopSound);
+        self.finish(ctx)
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        self.on_cook_btn(ctx)
+    }
+
+    fn on_sensors(self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        let halt = if self.cooling { OvenHalt::check_running(sensors, ctx) } else { OvenHalt::check_idle(sensors, ctx) };
+        if let Some(halt) = halt {
+            if self.cooling {
+                ctx.act(Action::Session(self.energy));
+            }
+            halt
+        } else if !sensors.lid && (self.alarm || self.cooling) { //Summary of the stopped cooking stays over the open lid, the outputs are off
+            ctx.act(Action::StopSound);
+            ctx.act(Action::Motor(false)); //Lid is taken off, cool-down is up to the user
+            if self.cooling {
+                ctx.act(Action::Session(self.energy));
+            }
+            Oven::from(LidOpen::new(ctx))
+        } else {
+            Oven::from(self)
+        }
+    }
+
+    fn on_tick(mut self, tick: &Tick, ctx: &mut Context) -> Oven {
+        let interval = if self.alarm { ctx.settings.alarm_interval as u32 * TICKS_PER_SECOND } else { 0 };
+        let duration = if self.alarm { ctx.settings.alarm_duration as u32 * TICKS_PER_MINUTE } else { 2 * SUMMARY_PERIOD }; //Stopped one is left after the summary
+        let passed = self.last_tick.map(|last| tick.now.wrapping_sub(last)).unwrap_or(0);
+        self.last_tick = Some(tick.now);
+        self.energy.add(passed, false, self.cooling);
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(SUMMARY_PERIOD) {
+            if (self.ticks / SUMMARY_PERIOD).is_multiple_of(2) {
+                ctx.act(Action::Message(self.message));
+            } else {
+                self.show_summary(ctx);
+            }
+        }
+        if self.ticks >= duration {
+            self.finish(ctx) //Last alarm is finished by the buzzer on its own
+        } else {
+            if interval > 0 && self.ticks.is_multiple_of(interval) {
+                ctx.act(Action::Sound(Sound::Done));
+            }
+            Oven::from(self)
+        }
+    }
+
+    fn on_pid(&mut self) {}
+}
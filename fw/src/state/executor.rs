@@ -0,0 +1,180 @@
+use embedded_hal::digital::v2::OutputPin;
+use crate::buzzer::BuzzerManager;
+use crate::display::{DisplayBackend, LcdDisplay, Timer};
+use crate::recovery::Checkpoint;
+use crate::settings::{Settings, TempUnit};
+use crate::statistics::Statistics;
+use crate::state::{Action, Actions};
+#[cfg(not(test))]
+use crate::storage::OvenStorage;
+
+/**
+Hardware, driven by the actions of the state machine.
+ */
+pub struct OvenControlHardware<SCREEN: DisplayBackend, BUZZER: OutputPin, LED, HEATER, MOTOR> {
+    pub display: LcdDisplay<SCREEN>,
+    pub buzzer: BuzzerManager<BUZZER>,
+    pub cook_ld: LED,
+    pub heater: HEATER,
+    pub motor: MOTOR,
+    pub settings: Settings
+}
+
+/**
+Flash records waiting to be written. Page erase stalls the CPU, so they are written by the lowest priority task
+after the transition is over, and repeated saves of a record are merged into one.
+ */
+#[derive(Default)]
+pub struct FlashWrites {
+    settings: Option<Settings>,
+    statistics: Option<Statistics>, //Latest snapshot
+    clear_checkpoint: bool,
+    checkpoint: Option<Checkpoint> //Appended after the clear, as it belongs to the next session
+}
+
+#[cfg(not(test))]
+impl FlashWrites {
+    pub fn store(self, storage: &mut OvenStorage) {
+        if let Some(settings) = self.settings {
+            settings.save(&mut storage.settings);
+        }
+        if let Some(statistics) = self.statistics {
+            statistics.save(&mut storage.statistics);
+        }
+        if self.clear_checkpoint {
+            Checkpoint::clear(&mut storage.recovery);
+        }
+        if let Some(checkpoint) = self.checkpoint {
+            if checkpoint.save(&mut storage.recovery).is_err() {
+                defmt::error!("Checkpoint isn't saved");
+                Checkpoint::clear(&mut storage.recovery); //Older checkpoint must not be resumed
+            }
+        }
+    }
+}
+
+/**
+Executes the actions of the state machine: drives the outputs and the display, keeps the timer,
+the requested temperature and the statistics, and collects the flash writes.
+Free of the HAL, so the host tests run the same code as the `StateManager`.
+ */
+pub struct ActionExecutor<SCREEN: DisplayBackend, BUZZER: OutputPin, LED, HEATER, MOTOR> {
+    pub hw: OvenControlHardware<SCREEN, BUZZER, LED, HEATER, MOTOR>,
+    pub statistics: Statistics,
+    pub time: u16, //Seconds
+    pub manual: bool, //Cooking without the countdown, time is the elapsed one then
+    pub temp_requested: u16, //In the display units
+    writes: FlashWrites
+}
+
+impl<SCREEN: DisplayBackend, BUZZER: OutputPin, LED: OutputPin, HEATER: OutputPin, MOTOR: OutputPin> ActionExecutor<SCREEN, BUZZER, LED, HEATER, MOTOR> {
+    pub fn new(hw: OvenControlHardware<SCREEN, BUZZER, LED, HEATER, MOTOR>, statistics: Statistics) -> Self {
+        let temp_requested = hw.settings.unit.limits().0;
+        let mut executor = ActionExecutor { hw, statistics, time: 0, manual: false, temp_requested, writes: FlashWrites::default() };
+        executor.hw.buzzer.set_muted(!executor.hw.settings.sound);
+        executor
+    }
+
+    /// Unit of the requested temperature
+    pub fn unit(&self) -> TempUnit {
+        self.hw.settings.unit
+    }
+
+    /// Draws the state line, `running` makes the manual mode timer the elapsed time
+    pub fn update_display(&mut self, temp_actual: u16, running: bool) {
+        let timer = match (self.manual, running) {
+            (false, _) => Timer::Countdown(self.time),
+            (true, true) => Timer::Elapsed(self.time),
+            (true, false) => Timer::Manual
+        };
+        self.hw.display.state(timer, temp_actual, self.temp_requested, self.unit());
+    }
+
+    /// Executes the actions in the order of the states, which switch the outputs off first.
+    /// Returns true if the settings were changed, so the inputs have to be configured again.
+    pub fn execute_all(&mut self, actions: Actions) -> bool {
+        let switched = |heater: bool, on: bool| actions.iter().any(|action| match action {
+            Action::Heater(state) => heater && *state == on,
+            Action::Motor(state) => !heater && *state == on,
+            _ => false
+        });
+        debug_assert!(!(switched(true, true) && switched(true, false)), "heater is switched both ways in a transition");
+        debug_assert!(!(switched(false, true) && switched(false, false)), "motor is switched both ways in a transition");
+        let settings = actions.iter().any(|action| matches!(action, Action::Settings(_)));
+        actions.into_iter().for_each(|action| self.execute(action));
+        settings
+    }
+
+    fn execute(&mut self, action: Action) {
+        let hw = &mut self.hw;
+        match action {
+            Action::Heater(on) => hw.heater.set_state(on.into()).unwrap_or_default(),
+            Action::Motor(on) => hw.motor.set_state((!on).into()).unwrap_or_default(), //Motor enable is inverted
+            Action::CookLed(on) => hw.cook_ld.set_state(on.into()).unwrap_or_default(),
+            Action::Buzzer(on) => hw.buzzer.set_output(on),
+            Action::Sound(sound) => hw.buzzer.play(sound),
+            Action::StopSound => hw.buzzer.stop(),
+            Action::Message(msg) => hw.display.message(msg),
+            Action::Text(text) => hw.display.message(&text),
+            Action::Icons(icons) => hw.display.icons(icons),
+            Action::Progress(elapsed, total) => hw.display.progress(elapsed, total),
+            Action::Error(msg) => hw.display.error_message(msg),
+            Action::Screen(top, bottom) => hw.display.screen(&top, &bottom),
+            Action::TestPattern => hw.display.test_pattern(),
+            Action::SetTime(time) => self.time = time,
+            Action::ClearTimer => {
+                self.time = 0;
+                self.manual = false;
+            },
+            Action::Settings(settings) => {
+                self.writes.settings = Some(settings);
+                self.temp_requested = hw.settings.unit.convert(self.temp_requested, settings.unit); //Keep the requested temperature over the unit change
+                hw.settings = settings;
+                hw.buzzer.set_muted(!settings.sound);
+            },
+            Action::Session(energy) => {
+                self.statistics.add_session(&energy, &self.hw.settings);
+                self.save_statistics();
+                self.clear_checkpoint();
+            },
+            Action::HeatUp(rate) => self.statistics.add_heat_up(rate), //Heater is running, saved with the session
+            Action::ResetHeatUp => {
+                self.statistics.reset_heat_up();
+                self.save_statistics();
+            },
+            Action::Checkpoint(checkpoint) => self.writes.checkpoint = Some(checkpoint), //Only the last one matters
+            Action::FaultLog(fault) => {
+                self.statistics.add_fault(fault);
+                self.save_statistics(); //Merged with the session of the halted cooking
+            },
+            Action::ClearCheckpoint => self.clear_checkpoint(),
+            Action::Restore(checkpoint) => {
+                self.clear_checkpoint(); //Resumed session starts a fresh journal
+                self.writes.checkpoint = Some(checkpoint);
+                self.time = checkpoint.time;
+                self.manual = checkpoint.manual;
+                self.temp_requested = TempUnit::Celsius.convert(checkpoint.temp, self.unit());
+            },
+            Action::LoadPreset(preset) => {
+                self.time = preset.time * 60;
+                self.manual = false;
+                self.temp_requested = TempUnit::Celsius.convert(preset.temp, self.unit());
+            }
+        }
+    }
+
+    pub fn save_statistics(&mut self) {
+        self.writes.statistics = Some(self.statistics);
+    }
+
+    /// Session is over, the checkpoints waiting for the write are dropped too
+    fn clear_checkpoint(&mut self) {
+        self.writes.clear_checkpoint = true;
+        self.writes.checkpoint = None;
+    }
+
+    /// Hands the pending flash writes over to the storage task
+    pub fn take_writes(&mut self) -> FlashWrites {
+        core::mem::take(&mut self.writes)
+    }
+}
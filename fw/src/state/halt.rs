@@ -1,65 +1,116 @@
-use crate::current_sensor::CurrentSensor;
-use crate::state::{Oven, OvenControl, OvenControlHardware};
-use crate::temp_sensor::TempSensor;
+use crate::buzzer::{Fault, Sound};
+use crate::temp_sensor::BoardHeat;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+
+const FAULT_REPEAT: u16 = 100; //Fault sound is repeated every 10 seconds
 
 /**
 Halt state. Triggered by any other state when error is detected.
+Heater, motor and cooking LED are switched off, the only way out is a reset.
+Fault sound is repeated until then.
 
 Can't set temp/time.
 Can't start cooking.
  */
 pub struct OvenHalt {
-    hw: OvenControlHardware
+    fault: Fault,
+    ticks: u16
 }
 
 impl OvenHalt {
-    pub fn overheating(mut hw: OvenControlHardware) -> ! {
-        hw.display.error_message("DEVICE OVERHEAT!");
-        loop {cortex_m::asm::nop()}
+    fn halt(ctx: &mut Context, msg: &'static str, fault: Fault) -> Oven {
+        ctx.shutdown();
+        ctx.act(Action::Error(msg));
+        ctx.act(Action::StopSound);
+        ctx.act(Action::Sound(Sound::Fault(fault)));
+        ctx.act(Action::FaultLog(fault));
+        Oven::from(OvenHalt{fault, ticks: 0})
+    }
+
+    pub(super) fn overheating(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, "DEVICE OVERHEAT!", Fault::Overheat)
+    }
+
+    pub(super) fn temp_error(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, "T SENSOR FAILURE", Fault::TempSensor)
+    }
+
+    pub(super) fn current_error(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, "C SENSOR FAILURE", Fault::CurrentSensor)
+    }
+
+    pub(super) fn motor_uncontrolled(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, " MOTOR CONTROL! ", Fault::MotorControl)
     }
 
-    pub fn temp_error(mut hw: OvenControlHardware) -> ! {
-        hw.display.error_message("T SENSOR FAILURE");
-        loop {cortex_m::asm::nop()}
+    pub(super) fn motor_failed(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, " MOTOR FAILURE! ", Fault::MotorFailure)
     }
 
-    pub fn current_error(mut hw: OvenControlHardware) -> ! {
-        hw.display.error_message("C SENSOR FAILURE");
-        loop {cortex_m::asm::nop()}
+    pub(super) fn motor_overload(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, " MOTOR OVERLOAD ", Fault::MotorOverload)
     }
 
-    pub fn motor_uncontrolled(mut hw: OvenControlHardware) -> ! {
-        hw.display.error_message(" MOTOR CONTROL! ");
-        loop {cortex_m::asm::nop()}
+    /// Thermocouple failed the power-on self test
+    pub(super) fn self_test_failed(ctx: &mut Context) -> Oven {
+        OvenHalt::halt(ctx, "SELF TEST: TEMP ", Fault::TempSensor)
     }
 
-    pub fn motor_failed(mut hw: OvenControlHardware) -> ! {
-        hw.display.error_message(" MOTOR FAILURE! ");
-        loop {cortex_m::asm::nop()}
+    /// Checks the sensors of the oven with the motor running
+    pub(super) fn check_running(sensors: &Sensors, ctx: &mut Context) -> Option<Oven> {
+        if sensors.temp_error {
+            Some(OvenHalt::temp_error(ctx))
+        } else if sensors.board == BoardHeat::Overheat {
+            Some(OvenHalt::overheating(ctx))
+        } else if sensors.current_error {
+            Some(OvenHalt::current_error(ctx))
+        } else if !sensors.motor_running {
+            Some(OvenHalt::motor_failed(ctx))
+        } else if sensors.motor_overloaded {
+            Some(OvenHalt::motor_overload(ctx))
+        } else {
+            None
+        }
     }
 
-    pub fn motor_overload(mut hw: OvenControlHardware) -> ! {
-        hw.display.error_message(" MOTOR OVERLOAD ");
-        loop {cortex_m::asm::nop()}
+    /// Checks the sensors of the idle oven, with the motor expected to be off
+    pub(super) fn check_idle(sensors: &Sensors, ctx: &mut Context) -> Option<Oven> {
+        if sensors.temp_error {
+            Some(OvenHalt::temp_error(ctx))
+        } else if sensors.board == BoardHeat::Overheat {
+            Some(OvenHalt::overheating(ctx))
+        } else if sensors.current_error {
+            Some(OvenHalt::current_error(ctx))
+        } else if !sensors.motor_standby {
+            Some(OvenHalt::motor_uncontrolled(ctx))
+        } else {
+            None
+        }
     }
 }
 
 impl OvenControl for OvenHalt {
-    fn on_cook_btn(self) -> Oven {
+    fn on_cook_btn(self, _ctx: &mut Context) -> Oven {
         Oven::from(self)
     }
 
-    fn on_sensors(self, _: bool, _: &TempSensor, _: &CurrentSensor) -> Oven {
+    fn on_double_press(self, _ctx: &mut Context) -> Oven {
         Oven::from(self)
     }
 
-    fn on_settings(self, _temp_actual: u16, _temp_requested: u16, _time: u16) -> (Oven, u16) {
-        (Oven::from(self), 0)
+    fn on_sensors(self, _: &Sensors, _: &mut Context) -> Oven {
+        Oven::from(self)
     }
 
-    fn on_pid(&mut self) {}
-
-    fn get_hw_ref(&mut self) -> &mut OvenControlHardware {
-        &mut self.hw
+    fn on_tick(mut self, _tick: &Tick, ctx: &mut Context) -> Oven {
+        self.ticks += 1;
+        if self.ticks >= FAULT_REPEAT {
+            self.ticks = 0;
+            ctx.act(Action::Sound(Sound::Fault(self.fault)));
+        }
+        ctx.act(Action::SetTime(0));
+        Oven::from(self)
     }
-}
\ No newline at end of file
+
+    fn on_pid(&mut self) {}
+}
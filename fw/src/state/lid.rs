@@ -1,8 +1,8 @@
-use crate::current_sensor::CurrentSensor;
-use crate::state::{Oven, OvenControl, OvenControlHardware};
+use crate::buzzer::Sound;
+use crate::display::{Glyph, StatusIcons};
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
 use crate::state::OvenHalt;
 use crate::state::OvenReady;
-use crate::temp_sensor::TempSensor;
 
 /**
  LidOpen state. Triggered by any other state when lid is open (detected by lid sensor).
@@ -10,46 +10,40 @@ use crate::temp_sensor::TempSensor;
  Can set temp/time.
  Can't start cooking.
 */
-pub struct LidOpen {
-    hw: OvenControlHardware
-}
+pub struct LidOpen {}
 
 impl LidOpen {
-    pub fn new(mut hw: OvenControlHardware) -> Self {
-        hw.display.message("Please close lid");
-        LidOpen{hw}
+    pub fn new(ctx: &mut Context) -> Self {
+        ctx.act(Action::Message("Close the lid"));
+        ctx.act(Action::Icons(StatusIcons { mode: Some(Glyph::LidOpen), ..StatusIcons::default() }));
+        LidOpen{}
     }
 }
 
 impl OvenControl for LidOpen{
-    fn on_cook_btn(self) -> Oven {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::Sound(Sound::Warning)); //Cooking can't be started with the open lid
         Oven::from(self)
     }
 
-    fn on_sensors(self, lid: bool, temp_sensor: &TempSensor, current_sensor: &CurrentSensor) -> Oven {
-        if temp_sensor.is_error() {
-            OvenHalt::temp_error(self.hw)
-        } else if temp_sensor.is_overheating() {
-            OvenHalt::overheating(self.hw)
-        } else if current_sensor.is_error() {
-            OvenHalt::current_error(self.hw)
-        } else if !current_sensor.is_standby() {
-            OvenHalt::motor_uncontrolled(self.hw)
-        } else if lid {
-            Oven::from(OvenReady::new(self.hw))
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::ClearTimer);
+        Oven::from(self)
+    }
+
+    fn on_sensors(self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        if let Some(halt) = OvenHalt::check_idle(sensors, ctx) {
+            halt
+        } else if sensors.lid {
+            Oven::from(OvenReady::new(ctx))
         } else {
             Oven::from(self)
         }
     }
 
-    fn on_settings(self, _temp_actual: u16, _temp_requested: u16, time: u16) -> (Oven, u16) {
-        (Oven::from(self), time)
+    fn on_tick(self, _tick: &Tick, _ctx: &mut Context) -> Oven {
+        Oven::from(self)
     }
 
     fn on_pid(&mut self) {}
-
-    fn get_hw_ref(&mut self) -> &mut OvenControlHardware {
-        &mut self.hw
-    }
-
-}
\ No newline at end of file
+}
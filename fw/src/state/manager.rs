@@ -1,55 +1,192 @@
+use core::fmt::Write;
+use heapless::String;
+use crate::board::{Buzzer, CookLd, HeaterEnable, MotorEnable, SpiBus, TcCs};
+use crate::button::Gesture;
 use crate::current_sensor::CurrentSensor;
-use crate::encoder::{EncoderReaderTIM1, EncoderReaderTIM3};
-use crate::state::{Oven, OvenControlHardware, OvenControl};
-use crate::state::ready::OvenReady;
+use crate::display::Screen;
+use stm32f3xx_hal::pac::{TIM1, TIM3};
+use crate::encoder::EncoderReader;
+use crate::state::{Actions, Event, Oven, Sensors, Tick, TICKS_PER_MINUTE};
+use crate::state::executor::{ActionExecutor, FlashWrites, OvenControlHardware};
+use crate::state::notice::Page;
+use crate::recovery::Checkpoint;
+use crate::reset::ResetCause;
+use crate::statistics::Statistics;
+use crate::storage::OvenStorage;
 use crate::temp_sensor::TempSensor;
 
+/**
+Hardware of the oven, with the board pins.
+ */
+pub type OvenHardware = OvenControlHardware<Screen, Buzzer, CookLd, HeaterEnable, MotorEnable>;
+
+/**
+Hardware layer of the state machine: polls inputs, turns them into events
+and executes the resulting actions.
+ */
 pub struct StateManager {
-    time: u16,
-    temp_requested: u16,
-    temp_actual: u16,
+    executor: ActionExecutor<Screen, Buzzer, CookLd, HeaterEnable, MotorEnable>,
+    temp_actual: u16, //In the display units
     temp_actual_raw: u16,
-    temp_enc: EncoderReaderTIM1,
-    time_enc: EncoderReaderTIM3,
+    temp_enc: EncoderReader<TIM1>,
+    time_enc: EncoderReader<TIM3>,
     state: Option<Oven>,
-    temp_sensor: TempSensor,
-    current_sensor: CurrentSensor
+    temp_sensor: TempSensor<SpiBus, TcCs>,
+    current_sensor: CurrentSensor,
+    idle_ticks: u32,
+    sleeping: bool
 }
 
 impl StateManager {
-    pub fn new(hw: OvenControlHardware, current_sensor: CurrentSensor, temp_enc: EncoderReaderTIM1, time_enc: EncoderReaderTIM3, temp_sensor: TempSensor) -> Self {
-        let initial_state = Some(Oven::from(OvenReady::new(hw)));
-        let mut manager = StateManager{time:0, temp_requested: 50, temp_actual: 0, temp_actual_raw: 0, temp_enc, time_enc, state: initial_state, temp_sensor, current_sensor};
-        manager.state.as_mut().unwrap().get_hw_ref().display.state(manager.time, manager.temp_actual, manager.temp_requested);
+    pub fn new(hw: OvenHardware, current_sensor: CurrentSensor, temp_enc: EncoderReader<TIM1>, time_enc: EncoderReader<TIM3>, temp_sensor: TempSensor<SpiBus, TcCs>, storage: &OvenStorage, service: bool) -> Self {
+        let statistics = Statistics::load(&storage.statistics);
+        let (initial_state, actions) = crate::state::start(&hw.settings, &statistics, Checkpoint::load(&storage.recovery), service);
+        let executor = ActionExecutor::new(hw, statistics);
+        let mut manager = StateManager{executor, temp_actual: 0, temp_actual_raw: 0, temp_enc, time_enc, state: Some(initial_state), temp_sensor, current_sensor, idle_ticks: 0, sleeping: false};
+        manager.execute_all(actions);
+        manager.apply_settings();
+        if manager.shows_state() {
+            manager.update_display();
+        }
         manager
     }
 
+    fn update_display(&mut self) {
+        let running = self.is_running();
+        self.executor.update_display(self.temp_actual, running);
+    }
+
+    /// Manual cooking is in progress, so the time is the elapsed one
+    fn is_running(&self) -> bool {
+        self.state.as_ref().is_some_and(Oven::is_running)
+    }
+
+    /// Sets the timer from the encoder, turning it below zero switches to the manual mode
+    fn read_time(&mut self) -> bool {
+        let executor = &mut self.executor;
+        let Some(value) = self.time_enc.read(executor.time) else {
+            return false;
+        };
+        if executor.manual {
+            if !self.state.as_ref().is_some_and(Oven::is_running) && value > 0 { //Turned up from the manual mode
+                executor.manual = false;
+                executor.time = 0;
+            }
+        } else if executor.time == 0 && value == 0 {
+            executor.manual = true;
+        } else {
+            executor.time = value;
+        }
+        true
+    }
+
+    /// Configures inputs and sensors according to the current settings
+    fn apply_settings(&mut self) {
+        let settings = self.executor.hw.settings;
+        let (low, high) = settings.unit.limits();
+        self.temp_enc.set_margins(low / settings.unit.step(), high / settings.unit.step());
+        self.temp_sensor.set_offset(settings.temp_offset as f32);
+        self.temp_sensor.set_board_limits(settings.board_limits());
+    }
+
+    /// State line is shown, not the whole screen one
+    fn shows_state(&self) -> bool {
+        self.state.as_ref().is_some_and(Oven::shows_state)
+    }
+
+    fn is_ready(&self) -> bool {
+        matches!(self.state, Some(Oven::OvenReady(_)))
+    }
+
+    /// Switches display back on, returns true if it was sleeping
+    fn wake_up(&mut self) -> bool {
+        self.idle_ticks = 0;
+        if self.sleeping {
+            self.sleeping = false;
+            self.executor.hw.display.set_sleep(false);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sensors(&self, lid: bool) -> Sensors {
+        Sensors {
+            lid,
+            temp_error: self.temp_sensor.is_error(),
+            temp_ready: self.temp_sensor.get_sensor().is_some(),
+            board: self.temp_sensor.board_heat(),
+            duty_limit: self.temp_sensor.duty_limit(),
+            current_error: self.current_sensor.is_error(),
+            motor_standby: self.current_sensor.is_standby(),
+            motor_running: self.current_sensor.is_running(),
+            motor_overloaded: self.current_sensor.is_overloaded(),
+            thermocouple: self.temp_sensor.get_sensor().unwrap_or(0.0) as i16,
+            thermocouple_raw: self.temp_sensor.get_raw().unwrap_or(0.0) as i16,
+            internal: self.temp_sensor.get_internal_temperature().unwrap_or(0.0) as i16,
+            millivolts: self.current_sensor.millivolts()
+        }
+    }
+
+    /// Runs the state machine with the event and executes the resulting actions
+    fn dispatch(&mut self, event: Event) {
+        if let Some(state) = self.state.take() {
+            let (state, actions) = crate::state::transition(state, event, &self.executor.hw.settings, &self.executor.statistics);
+            self.state = Some(state);
+            self.execute_all(actions);
+        }
+    }
+
+    fn execute_all(&mut self, actions: Actions) {
+        let unit = self.executor.unit();
+        if self.executor.execute_all(actions) {
+            if self.executor.unit() != unit {
+                self.temp_actual = 0; //Will be requantized on the next poll
+            }
+            self.apply_settings();
+        }
+    }
+
+    /// Hands the pending flash writes over to the storage task
+    pub fn take_writes(&mut self) -> FlashWrites {
+        self.executor.take_writes()
+    }
+
     pub fn adc_poll(&mut self, volts: f32) {
         self.current_sensor.add_value(volts);
     }
 
-    pub fn enc_poll(&mut self, lid: bool) {
+    /// Polls the inputs and updates the state, `now` is the monotonic time in milliseconds
+    pub fn enc_poll(&mut self, lid: bool, now: u32) {
         //Poll sensors
         self.temp_sensor.poll_sensor();
-        if let Some(o) = &mut self.state {
-            o.get_hw_ref().buzzer.on_timer();
-        }
+        self.executor.hw.buzzer.on_timer();
 
         //Check lid state
-        if self.state.is_some() { //Check the lid state
-            let lid_value_state = self.state.take().map(|o| Oven::from(o.on_sensors(lid, &self.temp_sensor, &self.current_sensor)));
-            self.state = lid_value_state;
-        }
+        self.dispatch(Event::Sensors(self.sensors(lid)));
 
-        let mut state_updated = self.temp_enc.read(self.temp_requested/5).map(|v| self.temp_requested = v * 5).is_some();
-        state_updated = state_updated || self.time_enc.read(self.time).map(|v| self.time = v).is_some();
+        let step = self.executor.unit().step();
+        let raw_encoders = matches!(self.state, Some(Oven::OvenMenu(_)) | Some(Oven::OvenService(_)));
+        let mut state_updated = false;
+        if raw_encoders { //Encoders are used for the menu navigation or the service mode outputs
+            let (navigation, value) = (self.time_enc.read_delta(), self.temp_enc.read_delta());
+            self.dispatch(Event::Encoders { navigation, value });
+        } else {
+            state_updated = self.temp_enc.read(self.executor.temp_requested/step).map(|v| self.executor.temp_requested = v * step).is_some();
+            state_updated = self.read_time() || state_updated;
+        }
+        if state_updated || !self.is_ready() {
+            self.wake_up();
+        }
 
         if let Some(measured_temp) = self.temp_sensor.get_sensor() {
             self.temp_actual_raw = measured_temp as u16; //Lets feed PID with actualy temp values
-            let q_value = if libm::fabsf(self.temp_requested as f32 - measured_temp) <= 5.0 {
-                self.temp_requested
+            let measured_value = self.executor.unit().from_celsius(measured_temp);
+            let temp_requested = self.executor.temp_requested;
+            let q_value = if libm::fabsf(temp_requested as f32 - measured_value) <= step as f32 {
+                temp_requested
             } else {
-                ((measured_temp/5.0) as u16) * 5 //Quantization by 5
+                ((measured_value/step as f32) as u16) * step //Quantization by the encoder step
             };
             if self.temp_actual != q_value {
                 self.temp_actual = q_value;
@@ -57,29 +194,72 @@ impl StateManager {
             }
         }
 
-        if self.state.is_some() {
-            let (settings_state, time) = self.state.take().unwrap().on_settings(self.temp_actual_raw, self.temp_requested, self.time);
-            if time != self.time {
-                self.time = time;
-                state_updated = true;
-            }
-            self.state = Some(settings_state);
+        let time = self.executor.time;
+        let shown = self.shows_state();
+        self.dispatch(Event::Tick(Tick { temp_actual: self.temp_actual_raw, temp_requested: self.executor.unit().to_celsius(self.executor.temp_requested), time, manual: self.executor.manual, now }));
+        state_updated = state_updated || time != self.executor.time || shown != self.shows_state(); //Full screen state is left
+
+        if state_updated && self.shows_state() {
+            self.update_display();
         }
 
-        if state_updated{
-            if let Some(o) = &mut self.state {
-                o.get_hw_ref().display.state(self.time, self.temp_actual, self.temp_requested);
+        let sleep_timeout = self.executor.hw.settings.sleep_timeout as u32 * TICKS_PER_MINUTE;
+        if self.is_ready() && sleep_timeout > 0 && !self.sleeping {
+            self.idle_ticks += 1;
+            if self.idle_ticks >= sleep_timeout {
+                self.sleeping = true;
+                self.executor.hw.display.set_sleep(true);
             }
         }
+    }
 
+    /// Shows the notice page over the current state
+    pub fn notice(&mut self, page: Page) {
+        if let Some(state) = self.state.take() {
+            let (state, actions) = crate::state::notice(state, page, &self.executor.hw.settings, &self.executor.statistics);
+            self.state = Some(state);
+            self.execute_all(actions);
+        }
+    }
+
+    /// Records the unexpected reset and shows its cause, the power-on is the expected one
+    pub fn on_reset(&mut self, cause: ResetCause) {
+        if cause == ResetCause::PowerOn {
+            return;
+        }
+        self.executor.statistics.add_reset(cause);
+        self.executor.save_statistics();
+        let (mut top, mut bottom): (String<16>, String<16>) = (String::new(), String::new());
+        write!(top, "Restarted by").unwrap_or_default();
+        write!(bottom, "{}", cause.name()).unwrap_or_default();
+        self.notice((top, bottom));
+    }
+
+    /// Copy of the current screen content
+    pub fn screen(&self) -> Screen {
+        self.executor.hw.display.backend().clone()
     }
 
     pub fn pid_poll(&mut self) {
-        self.state.as_mut().map(|o| o.on_pid());
+        self.dispatch(Event::Pid);
     }
 
-    pub fn on_cook_btn(&mut self) {
-        let cook_value_state = self.state.take().map(|o| Oven::from(o.on_cook_btn()));
-        self.state = cook_value_state;
+    /// Reports the lid opening at once, the rest of the inputs wait for the next poll
+    pub fn on_lid(&mut self, closed: bool) {
+        let shown = self.shows_state();
+        self.dispatch(Event::Sensors(self.sensors(closed)));
+        if shown != self.shows_state() && self.shows_state() {
+            self.update_display();
+        }
+    }
+
+    pub fn on_cook_btn(&mut self, gesture: Gesture) {
+        if self.wake_up() {
+            return; //First press only wakes the display up
+        }
+        self.dispatch(Event::Button(gesture));
+        if self.shows_state() { //Timer, preset or the menu exit
+            self.update_display();
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,354 @@
+use heapless::String;
+use core::fmt::Write;
+use crate::buzzer::Fault;
+use crate::reset::ResetCause;
+use crate::settings::{Settings, TempUnit, PRESETS};
+use crate::statistics::Statistics;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+use crate::state::halt::OvenHalt;
+use crate::state::ready::OvenReady;
+
+const DIAGNOSTICS_REFRESH: u8 = 10; //Sensor values are redrawn once a second
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MenuItem {
+    Unit,
+    Sound,
+    TempOffset,
+    Kp,
+    Ki,
+    Kd,
+    SleepTimeout,
+    AlarmInterval,
+    AlarmDuration,
+    AutoResume,
+    PauseTimeout,
+    ManualLimit,
+    HeaterPower,
+    MotorPower,
+    EnergyPrice,
+    BoardWarning,
+    BoardDerating,
+    BoardHalt,
+    CoolLimit,
+    CoolBoard,
+    CoolOven,
+    EnergyUse,
+    EnergyCost,
+    HeatUp,
+    Resets,
+    Faults,
+    PresetTemp(usize),
+    PresetTime(usize),
+    LoadPreset,
+    Diagnostics
+}
+
+const ITEMS: [MenuItem; 36] = [
+    MenuItem::LoadPreset,
+    MenuItem::Unit,
+    MenuItem::Sound,
+    MenuItem::AlarmInterval,
+    MenuItem::AlarmDuration,
+    MenuItem::AutoResume,
+    MenuItem::PauseTimeout,
+    MenuItem::ManualLimit,
+    MenuItem::CoolLimit,
+    MenuItem::CoolBoard,
+    MenuItem::CoolOven,
+    MenuItem::SleepTimeout,
+    MenuItem::PresetTemp(0),
+    MenuItem::PresetTime(0),
+    MenuItem::PresetTemp(1),
+    MenuItem::PresetTime(1),
+    MenuItem::PresetTemp(2),
+    MenuItem::PresetTime(2),
+    MenuItem::PresetTemp(3),
+    MenuItem::PresetTime(3),
+    MenuItem::TempOffset,
+    MenuItem::Kp,
+    MenuItem::Ki,
+    MenuItem::Kd,
+    MenuItem::BoardWarning,
+    MenuItem::BoardDerating,
+    MenuItem::BoardHalt,
+    MenuItem::HeaterPower,
+    MenuItem::MotorPower,
+    MenuItem::EnergyPrice,
+    MenuItem::EnergyUse,
+    MenuItem::EnergyCost,
+    MenuItem::HeatUp,
+    MenuItem::Resets,
+    MenuItem::Faults,
+    MenuItem::Diagnostics
+];
+
+fn adjust(value: u16, delta: i16, step: u16, low: u16, high: u16) -> u16 {
+    (value as i32 + delta as i32 * step as i32).clamp(low as i32, high as i32) as u16
+}
+
+/**
+Settings menu. Entered with the cook button long press from the Ready state.
+
+Time encoder selects the menu item, temperature encoder changes its value,
+cook button leaves the menu, storing the settings.
+Sensors are still checked, but the lid state is ignored.
+ */
+pub struct OvenMenu {
+    settings: Settings, //Edited copy, applied on the exit
+    statistics: Statistics,
+    item: usize,
+    preset: Option<usize>,
+    reset_heat_up: bool, //Heat-up reference is forgotten on the exit
+    refresh: u8,
+    sensors: Sensors //Last sensor values for the diagnostics
+}
+
+impl OvenMenu {
+    pub fn new(ctx: &mut Context) -> Self {
+        let menu = OvenMenu { settings: *ctx.settings, statistics: *ctx.statistics, item: 0, preset: None, reset_heat_up: false, refresh: 0, sensors: Sensors::default() };
+        menu.render(ctx);
+        menu
+    }
+
+    fn adjust(&mut self, delta: i16) {
+        let settings = &mut self.settings;
+        match ITEMS[self.item] {
+            MenuItem::Unit => settings.unit = if settings.unit == TempUnit::Celsius { TempUnit::Fahrenheit } else { TempUnit::Celsius },
+            MenuItem::Sound => settings.sound = !settings.sound,
+            MenuItem::TempOffset => settings.temp_offset = (settings.temp_offset + delta).clamp(-20, 20),
+            MenuItem::Kp => settings.k_p = adjust(settings.k_p, delta, 100, 0, 20000),
+            MenuItem::Ki => settings.k_i = adjust(settings.k_i, delta, 5, 0, 1000),
+            MenuItem::Kd => settings.k_d = adjust(settings.k_d, delta, 100, 0, 20000),
+            MenuItem::SleepTimeout => settings.sleep_timeout = adjust(settings.sleep_timeout, delta, 5, 0, 120),
+            MenuItem::AlarmInterval => settings.alarm_interval = adjust(settings.alarm_interval, delta, 5, 0, 300),
+            MenuItem::AlarmDuration => settings.alarm_duration = adjust(settings.alarm_duration, delta, 1, 1, 60),
+            MenuItem::AutoResume => settings.auto_resume = !settings.auto_resume,
+            MenuItem::PauseTimeout => settings.pause_timeout = adjust(settings.pause_timeout, delta, 1, 1, 60),
+            MenuItem::ManualLimit => settings.manual_limit = adjust(settings.manual_limit, delta, 10, 10, 240),
+            MenuItem::HeaterPower => settings.heater_power = adjust(settings.heater_power, delta, 50, 100, 3000),
+            MenuItem::MotorPower => settings.motor_power = adjust(settings.motor_power, delta, 1, 0, 200),
+            MenuItem::EnergyPrice => settings.energy_price = adjust(settings.energy_price, delta, 1, 0, 1000),
+            MenuItem::BoardWarning => settings.board_warning = adjust(settings.board_warning, delta, 1, 30, settings.board_derating), //Bands are kept ordered
+            MenuItem::BoardDerating => settings.board_derating = adjust(settings.board_derating, delta, 1, settings.board_warning, settings.board_halt),
+            MenuItem::BoardHalt => settings.board_halt = adjust(settings.board_halt, delta, 1, settings.board_derating, 85),
+            MenuItem::CoolLimit => settings.cool_limit = adjust(settings.cool_limit, delta, 1, 0, 30),
+            MenuItem::CoolBoard => settings.cool_board = adjust(settings.cool_board, delta, 1, 25, 60),
+            MenuItem::CoolOven => settings.cool_oven = adjust(settings.cool_oven, delta, 5, 30, 150),
+            MenuItem::EnergyUse | MenuItem::EnergyCost | MenuItem::Resets | MenuItem::Faults => {},
+            MenuItem::HeatUp => self.reset_heat_up = !self.reset_heat_up,
+            MenuItem::PresetTemp(index) => settings.presets[index].temp = adjust(settings.presets[index].temp, delta, 5, 50, 250),
+            MenuItem::PresetTime(index) => settings.presets[index].time = adjust(settings.presets[index].time, delta, 5, 5, 180),
+            MenuItem::LoadPreset => {
+                let selected = self.preset.map(|p| p as i16 + 1).unwrap_or(0) + delta; //Zero means no preset
+                self.preset = match selected.clamp(0, PRESETS as i16) {
+                    0 => None,
+                    p => Some(p as usize - 1)
+                };
+            },
+            MenuItem::Diagnostics => {}
+        }
+    }
+
+    fn render(&self, ctx: &mut Context) {
+        let settings = &self.settings;
+        let unit = settings.unit;
+        let mut title: String<16> = String::new();
+        let mut value: String<16> = String::new();
+        match ITEMS[self.item] {
+            MenuItem::Unit => {
+                write!(title, "Units").unwrap_or_default();
+                write!(value, "{}", if unit == TempUnit::Celsius { "Celsius" } else { "Fahrenheit" }).unwrap_or_default();
+            },
+            MenuItem::Sound => {
+                write!(title, "Sound").unwrap_or_default();
+                write!(value, "{}", if settings.sound { "On" } else { "Off" }).unwrap_or_default();
+            },
+            MenuItem::TempOffset => {
+                write!(title, "Temp calibration").unwrap_or_default();
+                write!(value, "{:+}°C", settings.temp_offset).unwrap_or_default();
+            },
+            MenuItem::Kp | MenuItem::Ki | MenuItem::Kd => {
+                let (name, gain) = match ITEMS[self.item] {
+                    MenuItem::Kp => ("Kp", settings.k_p),
+                    MenuItem::Ki => ("Ki", settings.k_i),
+                    _ => ("Kd", settings.k_d)
+                };
+                write!(title, "PID {}", name).unwrap_or_default();
+                write!(value, "{}.{:03}", gain / 1000, gain % 1000).unwrap_or_default();
+            },
+            MenuItem::SleepTimeout => {
+                write!(title, "Sleep timeout").unwrap_or_default();
+                if settings.sleep_timeout == 0 {
+                    write!(value, "Off").unwrap_or_default();
+                } else {
+                    write!(value, "{} min", settings.sleep_timeout).unwrap_or_default();
+                }
+            },
+            MenuItem::AlarmInterval => {
+                write!(title, "Alarm repeat").unwrap_or_default();
+                if settings.alarm_interval == 0 {
+                    write!(value, "Off").unwrap_or_default();
+                } else {
+                    write!(value, "{} s", settings.alarm_interval).unwrap_or_default();
+                }
+            },
+            MenuItem::AlarmDuration => {
+                write!(title, "Alarm duration").unwrap_or_default();
+                write!(value, "{} min", settings.alarm_duration).unwrap_or_default();
+            },
+            MenuItem::AutoResume => {
+                write!(title, "On lid closing").unwrap_or_default();
+                write!(value, "{}", if settings.auto_resume { "Resume" } else { "Wait for RUN" }).unwrap_or_default();
+            },
+            MenuItem::PauseTimeout => {
+                write!(title, "Pause timeout").unwrap_or_default();
+                write!(value, "{} min", settings.pause_timeout).unwrap_or_default();
+            },
+            MenuItem::ManualLimit => {
+                write!(title, "Manual limit").unwrap_or_default();
+                write!(value, "{} min", settings.manual_limit).unwrap_or_default();
+            },
+            MenuItem::CoolLimit => {
+                write!(title, "Cool-down limit").unwrap_or_default();
+                if settings.cool_limit == 0 {
+                    write!(value, "Off").unwrap_or_default();
+                } else {
+                    write!(value, "{} min", settings.cool_limit).unwrap_or_default();
+                }
+            },
+            MenuItem::CoolBoard | MenuItem::CoolOven => {
+                let (name, temp) = if ITEMS[self.item] == MenuItem::CoolBoard { ("board", settings.cool_board) } else { ("oven", settings.cool_oven) };
+                write!(title, "Cool-down {}", name).unwrap_or_default();
+                write!(value, "below {}°C", temp).unwrap_or_default();
+            },
+            MenuItem::HeaterPower => {
+                write!(title, "Heater power").unwrap_or_default();
+                write!(value, "{} W", settings.heater_power).unwrap_or_default();
+            },
+            MenuItem::MotorPower => {
+                write!(title, "Motor power").unwrap_or_default();
+                write!(value, "{} W", settings.motor_power).unwrap_or_default();
+            },
+            MenuItem::EnergyPrice => {
+                write!(title, "Energy price").unwrap_or_default();
+                write!(value, "{}.{:02}/kWh", settings.energy_price / 100, settings.energy_price % 100).unwrap_or_default();
+            },
+            MenuItem::BoardWarning | MenuItem::BoardDerating | MenuItem::BoardHalt => {
+                let (name, temp) = match ITEMS[self.item] {
+                    MenuItem::BoardWarning => ("warning", settings.board_warning),
+                    MenuItem::BoardDerating => ("derating", settings.board_derating),
+                    _ => ("halt", settings.board_halt)
+                };
+                write!(title, "Board {}", name).unwrap_or_default();
+                write!(value, "{}°C", temp).unwrap_or_default();
+            },
+            MenuItem::EnergyUse => {
+                let statistics = &self.statistics;
+                write!(title, "kWh last / total").unwrap_or_default();
+                write!(value, "{}.{:02} / {}", statistics.last_energy / 1000, statistics.last_energy % 1000 / 10, statistics.energy / 1000).unwrap_or_default();
+            },
+            MenuItem::EnergyCost => {
+                let statistics = &self.statistics;
+                write!(title, "Cost last / total").unwrap_or_default();
+                write!(value, "{}.{:02} / {}", statistics.last_cost / 100, statistics.last_cost % 100, statistics.cost / 100).unwrap_or_default();
+            },
+            MenuItem::HeatUp => {
+                let statistics = &self.statistics;
+                write!(title, "Heat-up ref/last").unwrap_or_default();
+                if self.reset_heat_up {
+                    write!(value, "Reset on exit").unwrap_or_default();
+                } else if statistics.heat_samples == 0 {
+                    write!(value, "Not measured").unwrap_or_default();
+                } else {
+                    write!(value, "{}.{} / {}.{} C/m", statistics.heat_reference / 10, statistics.heat_reference % 10, statistics.heat_last / 10, statistics.heat_last % 10).unwrap_or_default();
+                }
+            },
+            MenuItem::Resets => {
+                write!(title, "Resets: {}", self.statistics.resets).unwrap_or_default();
+                write!(value, "{}", ResetCause::from_code(self.statistics.last_reset).map(|cause| cause.name()).unwrap_or("None")).unwrap_or_default();
+            },
+            MenuItem::Faults => {
+                write!(title, "Faults: {}", self.statistics.faults).unwrap_or_default();
+                write!(value, "{}", Fault::from_code(self.statistics.last_fault).map(|fault| fault.name()).unwrap_or("None")).unwrap_or_default();
+            },
+            MenuItem::PresetTemp(index) => {
+                write!(title, "Preset {} temp", index + 1).unwrap_or_default();
+                write!(value, "{}°{}", TempUnit::Celsius.convert(settings.presets[index].temp, unit), unit.symbol()).unwrap_or_default();
+            },
+            MenuItem::PresetTime(index) => {
+                let time = settings.presets[index].time;
+                write!(title, "Preset {} time", index + 1).unwrap_or_default();
+                write!(value, "{}h{:02}m", time / 60, time % 60).unwrap_or_default(); //Hours form of the state line, as the presets are minutes
+            },
+            MenuItem::LoadPreset => {
+                write!(title, "Load preset").unwrap_or_default();
+                match self.preset {
+                    None => write!(value, "None").unwrap_or_default(),
+                    Some(index) => {
+                        let preset = settings.presets[index];
+                        write!(value, "P{} {}°{} {}h{:02}m", index + 1, TempUnit::Celsius.convert(preset.temp, unit), unit.symbol(), preset.time / 60, preset.time % 60).unwrap_or_default()
+                    }
+                }
+            },
+            MenuItem::Diagnostics => {
+                write!(title, "TC   CJ   ADC").unwrap_or_default();
+                let sensors = &self.sensors;
+                match sensors.millivolts { //Raw readings, the ADC isn't shown until sampled
+                    Some(millivolts) => write!(value, "{:<4} {:<4} {}mV", sensors.thermocouple_raw, sensors.internal, millivolts).unwrap_or_default(),
+                    None => write!(value, "{:<4} {:<4} --mV", sensors.thermocouple_raw, sensors.internal).unwrap_or_default()
+                }
+            }
+        }
+        ctx.act(Action::Screen(title, value));
+    }
+}
+
+impl OvenControl for OvenMenu {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::Settings(self.settings));
+        if let Some(index) = self.preset {
+            ctx.act(Action::LoadPreset(self.settings.presets[index]));
+        }
+        if self.reset_heat_up {
+            ctx.act(Action::ResetHeatUp);
+        }
+        Oven::from(OvenReady::new(ctx))
+    }
+
+    fn on_double_press(self, _ctx: &mut Context) -> Oven {
+        Oven::from(self)
+    }
+
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        self.sensors = *sensors;
+        OvenHalt::check_idle(sensors, ctx).unwrap_or_else(|| Oven::from(self))
+    }
+
+    fn on_tick(self, _tick: &Tick, _ctx: &mut Context) -> Oven {
+        Oven::from(self)
+    }
+
+    fn on_encoders(mut self, navigation: i16, value: i16, ctx: &mut Context) -> Oven {
+        let mut changed = false;
+        if navigation != 0 {
+            self.item = (self.item as i16 + navigation).clamp(0, ITEMS.len() as i16 - 1) as usize;
+            changed = true;
+        }
+        if value != 0 {
+            self.adjust(value);
+            changed = true;
+        }
+        if ITEMS[self.item] == MenuItem::Diagnostics {
+            self.refresh = self.refresh.saturating_sub(1);
+            changed = changed || self.refresh == 0;
+        }
+        if changed {
+            self.refresh = DIAGNOSTICS_REFRESH;
+            self.render(ctx);
+        }
+        Oven::from(self)
+    }
+
+    fn on_pid(&mut self) {}
+}
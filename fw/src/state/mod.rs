@@ -1,9 +1,13 @@
 use enum_dispatch::enum_dispatch;
-use stm32f3xx_hal::pac::TIM7;
-use crate::board::{Buzzer, CookLd, HeaterEnable, MotorEnable};
-use crate::buzzer::BuzzerManager;
-use crate::current_sensor::CurrentSensor;
-use crate::display::LcdDisplay;
+use heapless::{String, Vec};
+use crate::button::Gesture;
+use crate::buzzer::{Fault, Sound};
+use crate::display::StatusIcons;
+use crate::energy::Energy;
+use crate::settings::{Preset, Settings};
+use crate::recovery::Checkpoint;
+use crate::statistics::Statistics;
+use crate::temp_sensor::BoardHeat;
 use crate::state::cooking::Cooking;
 
 pub mod halt;
@@ -11,36 +15,238 @@ pub mod lid;
 pub mod ready;
 pub mod cooking;
 pub mod pre_run;
+pub mod executor;
+#[cfg(not(test))]
 pub mod manager;
+pub mod menu;
+pub mod done;
+pub mod paused;
+pub mod cool_down;
+pub mod notice;
+pub mod recovery;
+pub mod self_test;
+pub mod service;
+#[cfg(test)]
+mod tests;
 
+use crate::state::cool_down::OvenCoolDown;
+use crate::state::done::OvenDone;
 use crate::state::halt::OvenHalt;
 use crate::state::lid::LidOpen;
+use crate::state::menu::OvenMenu;
+use crate::state::notice::{OvenNotice, Page};
+use crate::state::paused::OvenPaused;
 use crate::state::pre_run::OvenPreRun;
 use crate::state::ready::OvenReady;
-use crate::temp_sensor::TempSensor;
+use crate::state::recovery::OvenRecovery;
+use crate::state::self_test::OvenSelfTest;
+use crate::state::service::OvenService;
 
-pub struct OvenControlHardware {
-    pub display: LcdDisplay<TIM7>,
-    pub buzzer: BuzzerManager<Buzzer>,
-    pub cook_ld: CookLd,
-    pub heater: HeaterEnable,
-    pub motor: MotorEnable
+const MAX_ACTIONS: usize = 14;
+pub const TICKS_PER_SECOND: u32 = 10; //State update timer runs in 100ms ticks
+pub const TICKS_PER_MINUTE: u32 = 60 * TICKS_PER_SECOND;
+
+/**
+Snapshot of the lid and sensors state.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Sensors {
+    pub lid: bool, //Lid is closed
+    pub temp_error: bool,
+    pub temp_ready: bool, //Thermocouple driver has responded
+    pub board: BoardHeat, //Temperature band of the TRIACs board
+    pub duty_limit: u8, //Percents of the heater duty allowed by the board temperature
+    pub current_error: bool,
+    pub motor_standby: bool,
+    pub motor_running: bool,
+    pub motor_overloaded: bool,
+    pub thermocouple: i16, //Degrees of Celsius
+    pub thermocouple_raw: i16, //Latest reading without the calibration offset, degrees of Celsius
+    pub internal: i16, //Degrees of Celsius
+    pub millivolts: Option<i16> //Latest current sensor sample, none until the ADC is read
+}
+
+/**
+Periodic update with the user settings, sent every 100ms.
+ */
+#[derive(Clone, Copy)]
+pub struct Tick {
+    pub temp_actual: u16, //Degrees of Celsius
+    pub temp_requested: u16, //Degrees of Celsius
+    pub time: u16, //Seconds, elapsed time for the manual cooking
+    pub manual: bool, //Cooking without the countdown
+    pub now: u32 //Monotonic clock, milliseconds
+}
+
+#[derive(Clone, Copy)]
+pub enum Event {
+    Button(Gesture),
+    Sensors(Sensors),
+    Tick(Tick),
+    /// Encoder movement, only used by the menu, as the temperature and time are set by the manager
+    Encoders { navigation: i16, value: i16 },
+    Pid
+}
+
+#[derive(Clone)]
+pub enum Action {
+    Heater(bool),
+    Motor(bool),
+    CookLed(bool),
+    /// Speaker driven directly, without a sound pattern
+    Buzzer(bool),
+    Sound(Sound),
+    StopSound,
+    Message(&'static str),
+    Text(String<16>),
+    Icons(StatusIcons),
+    Progress(u32, u32),
+    Error(&'static str),
+    Screen(String<16>, String<16>),
+    /// Every segment of the display lit
+    TestPattern,
+    SetTime(u16),
+    /// Clears the timer and the manual mode
+    ClearTimer,
+    /// Settings were changed, they have to be applied and stored
+    Settings(Settings),
+    LoadPreset(Preset),
+    /// Cooking session is over, its energy has to be accounted
+    Session(Energy),
+    /// Heat-up rate measured, tenths of degree of Celsius per minute
+    HeatUp(u16),
+    /// Learned heat-up reference has to be forgotten, after the heater replacement
+    ResetHeatUp,
+    /// Running session state, to be journaled for the power loss recovery
+    Checkpoint(Checkpoint),
+    /// Interrupted session isn't resumed
+    ClearCheckpoint,
+    /// Timer and temperature of the resumed session
+    Restore(Checkpoint),
+    /// Fault has to be recorded in the fault log, the outputs are off already
+    FaultLog(Fault)
+}
+
+pub type Actions = Vec<Action, MAX_ACTIONS>;
+
+/**
+Inputs and outputs of a single transition: current settings and statistics, and the actions produced by the states.
+ */
+pub struct Context<'a> {
+    pub settings: &'a Settings,
+    pub statistics: &'a Statistics,
+    pub actions: Actions
+}
+
+impl<'a> Context<'a> {
+    fn new(settings: &'a Settings, statistics: &'a Statistics) -> Self {
+        Context { settings, statistics, actions: Actions::new() }
+    }
+
+    fn act(&mut self, action: Action) {
+        let pushed = self.actions.push(action).is_ok();
+        debug_assert!(pushed, "more than {} actions in a transition", MAX_ACTIONS);
+    }
+
+    /// Switches all the outputs off, the heater first
+    fn shutdown(&mut self) {
+        self.act(Action::Heater(false));
+        self.act(Action::Motor(false));
+        self.act(Action::CookLed(false));
+    }
 }
 
 #[enum_dispatch]
 trait OvenControl {
-    fn on_cook_btn(self) -> Oven;
-    fn on_sensors(self, lid: bool, temp_sensor: &TempSensor, current_sensor: &CurrentSensor) -> Oven;
-    fn on_settings(self, temp_actual: u16, temp_requested: u16, time: u16) -> (Oven, u16);
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven;
+    /// Long press does the same as the short one, unless the state binds something else to it
+    fn on_long_press(self, ctx: &mut Context) -> Oven where Self: Sized {
+        self.on_cook_btn(ctx)
+    }
+    /// Double press clears the timer in the idle states, every state has to decide what it means
+    fn on_double_press(self, ctx: &mut Context) -> Oven;
+    fn on_sensors(self, sensors: &Sensors, ctx: &mut Context) -> Oven;
+    fn on_tick(self, tick: &Tick, ctx: &mut Context) -> Oven;
+    fn on_encoders(self, _navigation: i16, _value: i16, _ctx: &mut Context) -> Oven where Self: Sized + Into<Oven> {
+        self.into()
+    }
     fn on_pid(&mut self);
-    fn get_hw_ref(&mut self) -> &mut OvenControlHardware;
 }
 
 #[enum_dispatch(OvenControl)]
-enum Oven {
+pub enum Oven {
     OvenHalt,
     LidOpen,
     OvenReady,
     OvenPreRun,
-    Cooking
-}
\ No newline at end of file
+    Cooking,
+    OvenMenu,
+    OvenDone,
+    OvenPaused,
+    OvenCoolDown,
+    OvenNotice,
+    OvenRecovery,
+    OvenSelfTest,
+    OvenService
+}
+
+impl Oven {
+    /// State line is shown, not the whole screen one
+    pub fn shows_state(&self) -> bool {
+        !matches!(self, Oven::OvenMenu(_) | Oven::OvenHalt(_) | Oven::OvenNotice(_) | Oven::OvenRecovery(_) | Oven::OvenSelfTest(_) | Oven::OvenService(_))
+    }
+
+    /// Cooking session is in progress, so the manual mode timer is the elapsed time
+    pub fn is_running(&self) -> bool {
+        matches!(self, Oven::Cooking(_) | Oven::OvenPaused(_))
+    }
+}
+
+/// Initial state and its actions: the self test, followed by the resume offer if the cooking was interrupted, or the service mode
+pub fn start(settings: &Settings, statistics: &Statistics, checkpoint: Option<Checkpoint>, service: bool) -> (Oven, Actions) {
+    let mut ctx = Context::new(settings, statistics);
+    let state = if service {
+        Oven::from(OvenService::new(&mut ctx))
+    } else {
+        Oven::from(OvenSelfTest::new(checkpoint, &mut ctx))
+    };
+    (state, ctx.actions)
+}
+
+/// Shows the notice page, queuing it if there is a notice shown already
+pub fn notice(state: Oven, page: Page, settings: &Settings, statistics: &Statistics) -> (Oven, Actions) {
+    let mut ctx = Context::new(settings, statistics);
+    let state = match state {
+        Oven::OvenNotice(mut notice) => {
+            notice.add(page, &mut ctx);
+            Oven::from(notice)
+        },
+        Oven::OvenSelfTest(mut self_test) => {
+            self_test.add(page);
+            Oven::from(self_test)
+        },
+        Oven::OvenRecovery(recovery) => Oven::from(OvenNotice::new(page, Some(recovery), &mut ctx)),
+        Oven::OvenService(service) => Oven::from(service), //Service mode is left by the power cycle only, so it isn't interrupted
+        _ => Oven::from(OvenNotice::new(page, None, &mut ctx))
+    };
+    (state, ctx.actions)
+}
+
+/// Moves the state machine by the event. Has no side effects, the hardware is driven by the returned actions.
+pub fn transition(state: Oven, event: Event, settings: &Settings, statistics: &Statistics) -> (Oven, Actions) {
+    let mut ctx = Context::new(settings, statistics);
+    let state = match event {
+        Event::Button(Gesture::Short) => state.on_cook_btn(&mut ctx),
+        Event::Button(Gesture::Long) => state.on_long_press(&mut ctx),
+        Event::Button(Gesture::Double) => state.on_double_press(&mut ctx),
+        Event::Sensors(sensors) => state.on_sensors(&sensors, &mut ctx),
+        Event::Tick(tick) => state.on_tick(&tick, &mut ctx),
+        Event::Encoders { navigation, value } => state.on_encoders(navigation, value, &mut ctx),
+        Event::Pid => {
+            let mut state = state;
+            state.on_pid();
+            state
+        }
+    };
+    (state, ctx.actions)
+}
@@ -0,0 +1,84 @@
+use heapless::{Deque, String};
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+use crate::state::halt::OvenHalt;
+use crate::state::ready::OvenReady;
+use crate::state::recovery::OvenRecovery;
+
+const PAGE_TICKS: u16 = 100; //Each page is shown for 10 seconds, unless skipped by the cook button
+
+pub type Page = (String<16>, String<16>);
+pub type Pages = Deque<Page, 4>;
+
+/**
+Notices shown at the boot, like the report of the panic, that caused the last reset.
+Pages are shown one by one, the cook button skips to the next one.
+Resume of the interrupted cooking is offered after them.
+
+Sensors are still checked, but the lid state is ignored.
+Can't set temp/time.
+Can't start cooking.
+ */
+pub struct OvenNotice {
+    pages: Pages,
+    ticks: u16,
+    recovery: Option<OvenRecovery>
+}
+
+impl OvenNotice {
+    pub fn new(page: Page, recovery: Option<OvenRecovery>, ctx: &mut Context) -> Self {
+        let mut notice = OvenNotice { pages: Pages::new(), ticks: 0, recovery };
+        notice.add(page, ctx);
+        notice
+    }
+
+    /// Queues the page, it is shown at once if there was nothing shown
+    pub fn add(&mut self, page: Page, ctx: &mut Context) {
+        if self.pages.is_empty() {
+            ctx.act(Action::Screen(page.0.clone(), page.1.clone()));
+        }
+        self.pages.push_back(page).ok();
+    }
+
+    fn next(mut self, ctx: &mut Context) -> Oven {
+        self.pages.pop_front();
+        self.ticks = 0;
+        match self.pages.front() {
+            Some((top, bottom)) => {
+                ctx.act(Action::Screen(top.clone(), bottom.clone()));
+                Oven::from(self)
+            },
+            None => match self.recovery {
+                Some(recovery) => {
+                    recovery.show(ctx);
+                    Oven::from(recovery)
+                },
+                None => Oven::from(OvenReady::new(ctx))
+            }
+        }
+    }
+}
+
+impl OvenControl for OvenNotice {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        self.next(ctx)
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        self.next(ctx)
+    }
+
+    fn on_sensors(self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        OvenHalt::check_idle(sensors, ctx).unwrap_or_else(|| Oven::from(self))
+    }
+
+    fn on_tick(mut self, _tick: &Tick, ctx: &mut Context) -> Oven {
+        self.ticks += 1;
+        if self.ticks >= PAGE_TICKS {
+            self.next(ctx)
+        } else {
+            Oven::from(self)
+        }
+    }
+
+    fn on_pid(&mut self) {}
+}
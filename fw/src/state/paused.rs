@@ -0,0 +1,92 @@
+use crate::buzzer::Sound;
+use crate::display::{Glyph, StatusIcons};
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick, TICKS_PER_MINUTE};
+use crate::state::cooking::Cooking;
+use crate::state::done::OvenDone;
+use crate::state::halt::OvenHalt;
+
+/**
+Cooking paused by the lid lifting. Heater and motor are off, the cooking session
+(remaining time, preheating stage and PID state) is kept for the resume.
+
+Cooking is resumed on the lid closing or with the cook button after it, depending on the settings.
+The session is abandoned after the pause timeout or when the timer is set to zero.
+
+Can set temp/time.
+ */
+pub struct OvenPaused {
+    session: Cooking,
+    ticks: u32,
+    lid: bool
+}
+
+impl OvenPaused {
+    pub fn new(session: Cooking, ctx: &mut Context) -> Self {
+        OvenPaused::show_lid_open(ctx);
+        OvenPaused{session, ticks: 0, lid: false}
+    }
+
+    fn show_lid_open(ctx: &mut Context) {
+        ctx.act(Action::Message("   Paused    "));
+        ctx.act(Action::Icons(StatusIcons { mode: Some(Glyph::LidOpen), ..StatusIcons::default() }));
+    }
+
+    fn resume(self, ctx: &mut Context) -> Oven {
+        Oven::from(self.session.resume(ctx))
+    }
+
+    fn abandon(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::Session(self.session.energy()));
+        ctx.act(Action::Sound(Sound::Warning));
+        ctx.act(Action::ClearTimer);
+        Oven::from(OvenDone::stopped(self.session.energy(), false, ctx))
+    }
+}
+
+impl OvenControl for OvenPaused {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        if self.lid {
+            self.resume(ctx)
+        } else {
+            ctx.act(Action::Sound(Sound::Warning)); //Cooking can't be resumed with the open lid
+            Oven::from(self)
+        }
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        self.on_cook_btn(ctx) //Clearing the timer would abandon the cooking on the next tick
+    }
+
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        if let Some(halt) = OvenHalt::check_idle(sensors, ctx) {
+            ctx.act(Action::Session(self.session.energy()));
+            halt
+        } else if sensors.lid && !self.lid {
+            self.lid = true;
+            if ctx.settings.auto_resume {
+                self.resume(ctx)
+            } else {
+                ctx.act(Action::Message(" Paused, RUN "));
+                Oven::from(self)
+            }
+        } else {
+            if !sensors.lid && self.lid {
+                self.lid = false;
+                OvenPaused::show_lid_open(ctx);
+            }
+            Oven::from(self)
+        }
+    }
+
+    fn on_tick(mut self, tick: &Tick, ctx: &mut Context) -> Oven {
+        self.ticks += 1;
+        let timeout = ctx.settings.pause_timeout as u32 * TICKS_PER_MINUTE;
+        if (tick.time == 0 && !tick.manual) || self.ticks >= timeout {
+            self.abandon(ctx)
+        } else {
+            Oven::from(self)
+        }
+    }
+
+    fn on_pid(&mut self) {}
+}
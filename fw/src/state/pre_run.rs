@@ -1,56 +1,46 @@
-use crate::current_sensor::CurrentSensor;
-use crate::state::{Oven, OvenControl, OvenControlHardware};
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
 use crate::state::cooking::Cooking;
 use crate::state::halt::OvenHalt;
 use crate::state::lid::LidOpen;
 use crate::state::ready::OvenReady;
-use crate::temp_sensor::TempSensor;
 
-pub struct OvenPreRun {
-    hw: OvenControlHardware
-}
+pub struct OvenPreRun {}
 
 impl OvenPreRun {
-    pub fn new(mut hw: OvenControlHardware) -> Self {
-        hw.display.message("    Press RUN   ");
-        OvenPreRun{hw}
+    pub fn new(ctx: &mut Context) -> Self {
+        ctx.act(Action::Message("  Press RUN  "));
+        OvenPreRun{}
     }
 
 }
 
 impl OvenControl for OvenPreRun {
-    fn on_cook_btn(self) -> Oven {
-        Oven::from(Cooking::new(self.hw))
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        Oven::from(Cooking::new(ctx))
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::ClearTimer);
+        Oven::from(self)
     }
 
-    fn on_sensors(self, lid: bool, temp_sensor: &TempSensor, current_sensor: &CurrentSensor) -> Oven {
-        if temp_sensor.is_error() {
-            OvenHalt::temp_error(self.hw)
-        } else if temp_sensor.is_overheating() {
-            OvenHalt::overheating(self.hw)
-        } else if current_sensor.is_error() {
-            OvenHalt::current_error(self.hw)
-        } else if !current_sensor.is_standby() {
-            OvenHalt::motor_uncontrolled(self.hw)
-        } else if !lid {
-            Oven::from(LidOpen::new(self.hw))
+    fn on_sensors(self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        if let Some(halt) = OvenHalt::check_idle(sensors, ctx) {
+            halt
+        } else if !sensors.lid {
+            Oven::from(LidOpen::new(ctx))
         } else {
             Oven::from(self)
         }
     }
 
-    fn on_settings(self, _temp_actual: u16, _temp_requested: u16, time: u16) -> (Oven, u16) {
-        if time == 0 {
-            (Oven::from(OvenReady::new(self.hw)), time)
+    fn on_tick(self, tick: &Tick, ctx: &mut Context) -> Oven {
+        if tick.time == 0 && !tick.manual {
+            Oven::from(OvenReady::new(ctx))
         } else {
-            (Oven::from(self), time)
+            Oven::from(self)
         }
     }
 
     fn on_pid(&mut self) {}
-
-    fn get_hw_ref(&mut self) -> &mut OvenControlHardware {
-        &mut self.hw
-    }
-
-}
\ No newline at end of file
+}
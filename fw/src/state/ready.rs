@@ -1,55 +1,54 @@
-use crate::current_sensor::CurrentSensor;
-use crate::state::{Oven, OvenControl, OvenControlHardware};
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
 use crate::state::halt::OvenHalt;
 use crate::state::lid::LidOpen;
+use crate::state::menu::OvenMenu;
 use crate::state::pre_run::OvenPreRun;
-use crate::temp_sensor::TempSensor;
 
-pub struct OvenReady {
-    hw: OvenControlHardware
-}
+pub struct OvenReady {}
 
 impl OvenReady {
-    pub fn new(mut hw: OvenControlHardware) -> Self {
-        hw.display.message("     Ready      ");
-        OvenReady{hw}
+    pub fn new(ctx: &mut Context) -> Self {
+        if ctx.statistics.is_heater_weak() { //Maintenance warning, until the reference is reset
+            ctx.act(Action::Message(" Check heater"));
+        } else {
+            ctx.act(Action::Message("    Ready    "));
+        }
+        OvenReady{}
     }
 
 }
 
 impl OvenControl for OvenReady {
-    fn on_cook_btn(self) -> Oven {
+    fn on_cook_btn(self, _ctx: &mut Context) -> Oven {
         Oven::from(self)
     }
 
-    fn on_sensors(self, lid: bool, temp_sensor: &TempSensor, current_sensor: &CurrentSensor) -> Oven {
-        if temp_sensor.is_error() {
-            OvenHalt::temp_error(self.hw)
-        } else if temp_sensor.is_overheating() {
-            OvenHalt::overheating(self.hw)
-        } else if current_sensor.is_error() {
-            OvenHalt::current_error(self.hw)
-        } else if !current_sensor.is_standby() {
-            OvenHalt::motor_uncontrolled(self.hw)
-        } else if !lid {
-            Oven::from(LidOpen::new(self.hw))
+    fn on_long_press(self, ctx: &mut Context) -> Oven {
+        Oven::from(OvenMenu::new(ctx))
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        ctx.act(Action::ClearTimer);
+        Oven::from(self)
+    }
+
+    fn on_sensors(self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        if let Some(halt) = OvenHalt::check_idle(sensors, ctx) {
+            halt
+        } else if !sensors.lid {
+            Oven::from(LidOpen::new(ctx))
         } else {
             Oven::from(self)
         }
     }
 
-    fn on_settings(self, _temp_actual: u16, _temp_requested: u16, time: u16) -> (Oven, u16) {
-        if time == 0 {
-            (Oven::from(self), time)
+    fn on_tick(self, tick: &Tick, ctx: &mut Context) -> Oven {
+        if tick.time == 0 && !tick.manual {
+            Oven::from(self)
         } else {
-            (Oven::from(OvenPreRun::new(self.hw)), time)
+            Oven::from(OvenPreRun::new(ctx))
         }
     }
 
     fn on_pid(&mut self) {}
-
-    fn get_hw_ref(&mut self) -> &mut OvenControlHardware {
-        &mut self.hw
-    }
-
-}
\ No newline at end of file
+}
@@ -0,0 +1,107 @@
+use heapless::String;
+use core::fmt::Write;
+use crate::buzzer::Sound;
+use crate::recovery::Checkpoint;
+use crate::settings::TempUnit;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+use crate::state::cooking::Cooking;
+use crate::state::halt::OvenHalt;
+use crate::state::ready::OvenReady;
+
+const OFFER_TICKS: u16 = 600; //Resume is offered for a minute
+const MIN_TEMP: u16 = 50; //Degrees of Celsius, the oven has cooled down below it, so the power was off for too long
+const MAX_DROP: u16 = 60; //Degrees of Celsius below the setpoint
+
+/**
+Cooking session was interrupted by the reset. If the oven is still hot, so the power was lost
+only for a short time, resuming the session is offered.
+
+Cook button resumes the cooking, double or long press declines it.
+Can't set temp/time.
+ */
+pub struct OvenRecovery {
+    checkpoint: Checkpoint,
+    ticks: u16,
+    offered: bool,
+    lid: bool
+}
+
+impl OvenRecovery {
+    /// Resume is offered on the first tick, once the oven temperature is known
+    pub fn new(checkpoint: Checkpoint) -> Self {
+        OvenRecovery { checkpoint, ticks: 0, offered: false, lid: false }
+    }
+
+    /// Shows the offer again, after the boot notices
+    pub fn show(&self, ctx: &mut Context) {
+        if self.offered {
+            self.offer(ctx);
+        }
+    }
+
+    fn offer(&self, ctx: &mut Context) {
+        let checkpoint = &self.checkpoint;
+        let unit = ctx.settings.unit;
+        let mut top: String<16> = String::new();
+        let mut bottom: String<16> = String::new();
+        if checkpoint.manual {
+            write!(top, "Resume manual?").unwrap_or_default();
+        } else {
+            write!(top, "Resume {} min?", checkpoint.time.div_ceil(60)).unwrap_or_default();
+        }
+        write!(bottom, "{}°{} press RUN", TempUnit::Celsius.convert(checkpoint.temp, unit), unit.symbol()).unwrap_or_default();
+        ctx.act(Action::Screen(top, bottom));
+    }
+
+    fn decline(ctx: &mut Context) -> Oven {
+        ctx.act(Action::ClearCheckpoint);
+        Oven::from(OvenReady::new(ctx))
+    }
+}
+
+impl OvenControl for OvenRecovery {
+    fn on_cook_btn(self, ctx: &mut Context) -> Oven {
+        if !self.offered {
+            Oven::from(self)
+        } else if self.lid {
+            ctx.act(Action::Restore(self.checkpoint));
+            Oven::from(Cooking::restore(&self.checkpoint, ctx))
+        } else {
+            ctx.act(Action::Sound(Sound::Warning)); //Cooking can't be resumed with the open lid
+            Oven::from(self)
+        }
+    }
+
+    fn on_long_press(self, ctx: &mut Context) -> Oven {
+        OvenRecovery::decline(ctx)
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        OvenRecovery::decline(ctx)
+    }
+
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        self.lid = sensors.lid;
+        OvenHalt::check_idle(sensors, ctx).unwrap_or_else(|| Oven::from(self))
+    }
+
+    fn on_tick(mut self, tick: &Tick, ctx: &mut Context) -> Oven {
+        let temp = self.checkpoint.temp;
+        if !self.offered {
+            if tick.temp_actual < MIN_TEMP || tick.temp_actual + MAX_DROP < temp {
+                return OvenRecovery::decline(ctx);
+            }
+            self.offered = true;
+            self.offer(ctx);
+            ctx.act(Action::Sound(Sound::Warning));
+        }
+        self.ticks += 1;
+        if self.ticks >= OFFER_TICKS {
+            OvenRecovery::decline(ctx)
+        } else {
+            Oven::from(self)
+        }
+    }
+
+    fn on_pid(&mut self) {}
+}
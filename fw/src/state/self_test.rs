@@ -0,0 +1,183 @@
+use heapless::String;
+use core::fmt::Write;
+use crate::buzzer::Fault;
+use crate::recovery::Checkpoint;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+use crate::state::halt::OvenHalt;
+use crate::state::notice::{OvenNotice, Page, Pages};
+use crate::state::ready::OvenReady;
+use crate::state::recovery::OvenRecovery;
+
+const LCD_TICKS: u16 = 10; //Test pattern is shown for a second
+const SENSOR_TICKS: u16 = 30; //Thermocouple driver has to respond in 3 seconds
+const PULSE_TICKS: u16 = 15; //Motor pulse length
+const SUMMARY_TICKS: u16 = 20; //Results are shown for 2 seconds
+const INTERNAL_RANGE: (i16, i16) = (-10, 70); //Plausible cold junction temperature, degrees of Celsius
+const THERMOCOUPLE_RANGE: (i16, i16) = (-10, 300);
+const PULSE_MILLIVOLTS: i16 = 3; //Current sensor change of the running motor
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Lcd,
+    TempSensor,
+    Motor,
+    Summary
+}
+
+/**
+Power-on self test, before the oven gets ready.
+
+Shows the LCD test pattern, checks that the thermocouple driver responds with the plausible values,
+samples the current sensor baseline with the motor off and pulses the motor to see the current change.
+The motor is in the lid, so its check is skipped, when the lid is open. Results are summarized on the display.
+Failed thermocouple halts the oven. Current sensor and motor failures are only reported,
+as the motor protection relies on the current sensor calibration. Failures are recorded in the fault log.
+
+Boot notices and the resume offer are shown after the test.
+ */
+pub struct OvenSelfTest {
+    step: Step,
+    ticks: u16,
+    sensors: Sensors,
+    baseline: Option<i16>, //Current sensor millivolts with the motor off, none if the sensor has failed
+    pulse: bool, //Motor has been pulsed, the lid was closed
+    changed: bool, //Current has changed during the pulse
+    failed: bool,
+    pages: Pages,
+    checkpoint: Option<Checkpoint>
+}
+
+impl OvenSelfTest {
+    pub fn new(checkpoint: Option<Checkpoint>, ctx: &mut Context) -> Self {
+        ctx.act(Action::TestPattern);
+        OvenSelfTest { step: Step::Lcd, ticks: 0, sensors: Sensors::default(), baseline: None, pulse: false, changed: false, failed: false, pages: Pages::new(), checkpoint }
+    }
+
+    /// Queues the boot notice, shown once the test is over
+    pub fn add(&mut self, page: Page) {
+        self.pages.push_back(page).ok();
+    }
+
+    fn show_step(name: &str, ctx: &mut Context) {
+        let mut top: String<16> = String::new();
+        let mut bottom: String<16> = String::new();
+        write!(top, "Self test").unwrap_or_default();
+        write!(bottom, "{}", name).unwrap_or_default();
+        ctx.act(Action::Screen(top, bottom));
+    }
+
+    /// Records the failure and reports it after the test
+    fn fail(&mut self, fault: Fault, ctx: &mut Context) {
+        let mut top: String<16> = String::new();
+        let mut bottom: String<16> = String::new();
+        write!(top, "Self test failed").unwrap_or_default();
+        write!(bottom, "{}", fault.name()).unwrap_or_default();
+        self.pages.push_front((top, bottom)).ok();
+        self.failed = true;
+        ctx.act(Action::FaultLog(fault));
+    }
+
+    /// Shows the result of each check: ok, failed or skipped
+    fn show_summary(&self, ctx: &mut Context) {
+        let current = if self.baseline.is_some() { "ok" } else { "BAD" };
+        let motor = match (self.pulse, self.changed) {
+            (false, _) => "--", //Lid was open or the current can't be measured
+            (true, true) => "ok",
+            (true, false) => "BAD"
+        };
+        let mut top: String<16> = String::new();
+        let mut bottom: String<16> = String::new();
+        write!(top, "Self test {}", if self.failed { "failed" } else { "passed" }).unwrap_or_default();
+        write!(bottom, "Cur:{} Mot:{}", current, motor).unwrap_or_default();
+        ctx.act(Action::Screen(top, bottom));
+    }
+
+    fn is_plausible(sensors: &Sensors) -> bool {
+        let in_range = |value: i16, (low, high): (i16, i16)| (low..=high).contains(&value);
+        sensors.temp_ready && !sensors.temp_error && in_range(sensors.internal, INTERNAL_RANGE) && in_range(sensors.thermocouple, THERMOCOUPLE_RANGE)
+    }
+
+    /// Test is over, notices and the resume offer follow
+    fn finish(self, ctx: &mut Context) -> Oven {
+        let recovery = self.checkpoint.map(OvenRecovery::new);
+        let mut pages = self.pages.into_iter();
+        match (pages.next(), recovery) {
+            (Some(page), recovery) => {
+                let mut notice = OvenNotice::new(page, recovery, ctx);
+                pages.for_each(|page| notice.add(page, ctx));
+                Oven::from(notice)
+            },
+            (None, Some(recovery)) => Oven::from(recovery),
+            (None, None) => Oven::from(OvenReady::new(ctx))
+        }
+    }
+}
+
+impl OvenControl for OvenSelfTest {
+    fn on_cook_btn(self, _ctx: &mut Context) -> Oven {
+        Oven::from(self)
+    }
+
+    fn on_double_press(self, _ctx: &mut Context) -> Oven {
+        Oven::from(self)
+    }
+
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        self.sensors = *sensors;
+        if self.step == Step::Motor { //Motor is pulsed, it isn't idle
+            Oven::from(self)
+        } else {
+            OvenHalt::check_idle(sensors, ctx).unwrap_or_else(|| Oven::from(self))
+        }
+    }
+
+    fn on_tick(mut self, _tick: &Tick, ctx: &mut Context) -> Oven {
+        self.ticks += 1;
+        let sensors = self.sensors;
+        match self.step {
+            Step::Lcd if self.ticks >= LCD_TICKS => {
+                self.step = Step::TempSensor;
+                self.ticks = 0;
+                OvenSelfTest::show_step("Temp sensor", ctx);
+            },
+            Step::Lcd => {},
+            Step::TempSensor if OvenSelfTest::is_plausible(&sensors) => {
+                self.step = Step::Motor;
+                self.ticks = 0;
+                match sensors.millivolts {
+                    Some(millivolts) if !sensors.current_error => {
+                        self.baseline = Some(millivolts);
+                        if sensors.lid { //Motor is in the lid, so it isn't started while the lid is off
+                            self.pulse = true;
+                            ctx.act(Action::Motor(true));
+                            OvenSelfTest::show_step("Motor", ctx);
+                        }
+                    },
+                    _ => self.fail(Fault::CurrentSensor, ctx) //No samples of the current sensor
+                }
+            },
+            Step::TempSensor if self.ticks >= SENSOR_TICKS => return OvenHalt::self_test_failed(ctx),
+            Step::TempSensor => {},
+            Step::Motor => {
+                let baseline = self.baseline.unwrap_or_default();
+                self.changed = self.changed || sensors.millivolts.is_some_and(|millivolts| (millivolts - baseline).abs() >= PULSE_MILLIVOLTS);
+                if !self.pulse || self.ticks >= PULSE_TICKS {
+                    if self.pulse {
+                        ctx.act(Action::Motor(false));
+                        if !self.changed {
+                            self.fail(Fault::MotorFailure, ctx);
+                        }
+                    }
+                    self.step = Step::Summary;
+                    self.ticks = 0;
+                    self.show_summary(ctx);
+                }
+            },
+            Step::Summary if self.ticks >= SUMMARY_TICKS => return self.finish(ctx),
+            Step::Summary => {}
+        }
+        Oven::from(self)
+    }
+
+    fn on_pid(&mut self) {}
+}
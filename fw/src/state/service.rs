@@ -0,0 +1,157 @@
+use heapless::String;
+use core::fmt::Write;
+use crate::buzzer::Sound;
+use crate::state::{Action, Context, Oven, OvenControl, Sensors, Tick};
+use crate::temp_sensor::BoardHeat;
+
+const HEATER_TICKS: u16 = 50; //Heater is switched off on its own in 5 seconds
+const REFRESH_TICKS: u8 = 5; //Raw values are redrawn twice a second
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Motor,
+    Heater,
+    Led,
+    Buzzer
+}
+
+const OUTPUTS: [Output; 4] = [Output::Motor, Output::Heater, Output::Led, Output::Buzzer];
+
+impl Output {
+    fn name(&self) -> &'static str {
+        match self {
+            Output::Motor => "Motor",
+            Output::Heater => "Heater",
+            Output::Led => "LED",
+            Output::Buzzer => "Buzzer"
+        }
+    }
+
+    fn action(&self, on: bool) -> Action {
+        match self {
+            Output::Motor => Action::Motor(on),
+            Output::Heater => Action::Heater(on),
+            Output::Led => Action::CookLed(on),
+            Output::Buzzer => Action::Buzzer(on)
+        }
+    }
+}
+
+/**
+Service mode, entered by holding the cook button at the power-up. Left by the power cycle only.
+
+Top line shows the raw thermocouple, cold junction and current sensor values.
+Time encoder selects the output, temperature encoder switches it on (clockwise) or off,
+cook button switches all the outputs off.
+
+Heater is switched off on its own in a few seconds, and it is never on with the lid open,
+the sensor failure or the board overheat. Sensor faults don't halt the oven, as they are what is being repaired.
+ */
+pub struct OvenService {
+    selected: usize,
+    states: [bool; OUTPUTS.len()],
+    heater_ticks: u16,
+    refresh: u8,
+    sensors: Sensors
+}
+
+impl OvenService {
+    pub fn new(ctx: &mut Context) -> Self {
+        ctx.shutdown();
+        let service = OvenService { selected: 0, states: [false; OUTPUTS.len()], heater_ticks: 0, refresh: 0, sensors: Sensors::default() };
+        service.render(ctx);
+        service
+    }
+
+    fn is_on(&self, output: Output) -> bool {
+        self.states[output as usize]
+    }
+
+    fn set(&mut self, output: Output, on: bool, ctx: &mut Context) {
+        if self.is_on(output) != on {
+            self.states[output as usize] = on;
+            ctx.act(output.action(on));
+        }
+        if output == Output::Heater {
+            self.heater_ticks = 0;
+        }
+    }
+
+    /// Heater may only run with the closed lid and the working sensors
+    fn is_heater_safe(sensors: &Sensors) -> bool {
+        sensors.lid && sensors.temp_ready && !sensors.temp_error && sensors.board != BoardHeat::Overheat
+    }
+
+    fn render(&self, ctx: &mut Context) {
+        let sensors = &self.sensors;
+        let output = OUTPUTS[self.selected];
+        let mut top: String<16> = String::new();
+        let mut bottom: String<16> = String::new();
+        match sensors.millivolts { //Raw readings, the ADC isn't shown until sampled
+            Some(millivolts) => write!(top, "T{} C{} {}mV", sensors.thermocouple_raw, sensors.internal, millivolts).unwrap_or_default(),
+            None => write!(top, "T{} C{} --mV", sensors.thermocouple_raw, sensors.internal).unwrap_or_default()
+        }
+        write!(bottom, "{:<6} {:<3} ", output.name(), if self.is_on(output) { "on" } else { "off" }).unwrap_or_default();
+        for (index, letter) in ['m', 'h', 'l', 'b'].into_iter().enumerate() { //Uppercase for the outputs switched on
+            bottom.push(if self.states[index] { letter.to_ascii_uppercase() } else { letter }).unwrap_or_default();
+        }
+        ctx.act(Action::Screen(top, bottom));
+    }
+}
+
+impl OvenControl for OvenService {
+    fn on_cook_btn(mut self, ctx: &mut Context) -> Oven {
+        OUTPUTS.into_iter().for_each(|output| self.set(output, false, ctx));
+        self.render(ctx);
+        Oven::from(self)
+    }
+
+    fn on_double_press(self, ctx: &mut Context) -> Oven {
+        self.on_cook_btn(ctx)
+    }
+
+    fn on_sensors(mut self, sensors: &Sensors, ctx: &mut Context) -> Oven {
+        self.sensors = *sensors;
+        if self.is_on(Output::Heater) && !OvenService::is_heater_safe(sensors) {
+            self.set(Output::Heater, false, ctx);
+            self.render(ctx);
+        }
+        Oven::from(self)
+    }
+
+    fn on_tick(mut self, _tick: &Tick, ctx: &mut Context) -> Oven {
+        if self.is_on(Output::Heater) {
+            self.heater_ticks += 1;
+            if self.heater_ticks >= HEATER_TICKS {
+                self.set(Output::Heater, false, ctx);
+            }
+        }
+        self.refresh = self.refresh.saturating_sub(1);
+        if self.refresh == 0 {
+            self.refresh = REFRESH_TICKS;
+            self.render(ctx);
+        }
+        Oven::from(self)
+    }
+
+    fn on_encoders(mut self, navigation: i16, value: i16, ctx: &mut Context) -> Oven {
+        if navigation != 0 {
+            self.selected = (self.selected as i16 + navigation).clamp(0, OUTPUTS.len() as i16 - 1) as usize;
+        }
+        if value != 0 {
+            let output = OUTPUTS[self.selected];
+            let on = value > 0;
+            if on && output == Output::Heater && !OvenService::is_heater_safe(&self.sensors) {
+                ctx.act(Action::Sound(Sound::Warning)); //Heater can't be switched on with the open lid
+            } else {
+                self.set(output, on, ctx);
+            }
+        }
+        if navigation != 0 || value != 0 {
+            self.render(ctx);
+        }
+        Oven::from(self)
+    }
+
+    fn on_pid(&mut self) {}
+}
@@ -0,0 +1,257 @@
+use std::vec::Vec;
+use core::convert::Infallible;
+use embedded_hal::digital::v2::OutputPin;
+use crate::button::Gesture;
+use crate::buzzer::BuzzerManager;
+use crate::current_sensor::CurrentSensor;
+use crate::display::{LcdDisplay, Screen, LINES};
+use crate::recovery::Checkpoint;
+use crate::settings::Settings;
+use crate::statistics::Statistics;
+use crate::state::{Action, Actions, Event, Oven, Sensors, Tick, MAX_ACTIONS, TICKS_PER_SECOND};
+use crate::state::executor::{ActionExecutor, OvenControlHardware};
+use crate::state::notice::Page;
+
+mod properties;
+mod screens;
+mod transitions;
+
+const TICK_MS: u32 = 1000 / TICKS_PER_SECOND;
+const BOOT_TICKS: u32 = 100; //Self test is over in a few seconds
+const IDLE_MILLIVOLTS: i16 = 100; //Current sensor output with the motor off
+const MOTOR_MILLIVOLTS: i16 = 20; //Current of the running motor
+
+/**
+Output pin of the board, keeps its level.
+ */
+pub struct TestPin {
+    high: bool
+}
+
+impl OutputPin for TestPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.high = true;
+        Ok(())
+    }
+}
+
+/**
+Oven on the host: the pure state machine with the actions executed by the `StateManager` executor
+on the pins and the display in the memory, and the sensors made up by the test.
+ */
+pub struct TestOven {
+    state: Option<Oven>,
+    executor: ActionExecutor<Screen, TestPin, TestPin, TestPin, TestPin>,
+    pub thermocouple: i16, //Oven temperature, degrees of Celsius
+    pub lid: bool,
+    pub now: u32, //Milliseconds
+    current_sensor: CurrentSensor,
+    pub adc: bool, //Current sensor is sampled
+    pub log: Vec<Action> //Every action executed
+}
+
+impl TestOven {
+    /// Powered-on oven, running the self test
+    pub fn new(settings: Settings, checkpoint: Option<Checkpoint>) -> Self {
+        TestOven::start(settings, checkpoint, false)
+    }
+
+    /// Oven powered on with the cook button held
+    pub fn service(settings: Settings) -> Self {
+        TestOven::start(settings, None, true)
+    }
+
+    fn start(settings: Settings, checkpoint: Option<Checkpoint>, service: bool) -> Self {
+        let statistics = Statistics::default();
+        let (state, actions) = crate::state::start(&settings, &statistics, checkpoint, service);
+        let hw = OvenControlHardware { display: LcdDisplay::new(Screen::default()), buzzer: BuzzerManager::new(TestPin { high: true }),
+            cook_ld: TestPin { high: false }, heater: TestPin { high: false }, motor: TestPin { high: true }, settings }; //Speaker and motor enable are inverted
+        let mut oven = TestOven { state: Some(state), executor: ActionExecutor::new(hw, statistics), thermocouple: 25, lid: true, now: 0,
+            current_sensor: CurrentSensor::new(), adc: true, log: Vec::new() };
+        oven.execute_all(actions);
+        oven.update_display();
+        oven
+    }
+
+    /// Oven past the self test, in the Ready state
+    pub fn ready(settings: Settings) -> Self {
+        let mut oven = TestOven::new(settings, None);
+        for _ in 0..BOOT_TICKS {
+            if matches!(oven.state(), Oven::OvenReady(_)) {
+                break;
+            }
+            oven.tick();
+        }
+        assert!(matches!(oven.state(), Oven::OvenReady(_)), "self test isn't passed");
+        oven
+    }
+
+    pub fn state(&self) -> &Oven {
+        self.state.as_ref().expect("state is always put back")
+    }
+
+    pub fn state_name(&self) -> &'static str {
+        match self.state() {
+            Oven::OvenHalt(_) => "Halt",
+            Oven::LidOpen(_) => "LidOpen",
+            Oven::OvenReady(_) => "Ready",
+            Oven::OvenPreRun(_) => "PreRun",
+            Oven::Cooking(_) => "Cooking",
+            Oven::OvenMenu(_) => "Menu",
+            Oven::OvenDone(_) => "Done",
+            Oven::OvenPaused(_) => "Paused",
+            Oven::OvenCoolDown(_) => "CoolDown",
+            Oven::OvenNotice(_) => "Notice",
+            Oven::OvenRecovery(_) => "Recovery",
+            Oven::OvenSelfTest(_) => "SelfTest",
+            Oven::OvenService(_) => "Service"
+        }
+    }
+
+    /// Healthy sensors, the current follows the motor output once the ADC is sampled
+    pub fn sensors(&self) -> Sensors {
+        Sensors {
+            lid: self.lid,
+            temp_ready: true,
+            duty_limit: 100,
+            motor_standby: !self.motor(),
+            motor_running: self.motor(),
+            thermocouple: self.thermocouple,
+            thermocouple_raw: self.thermocouple - self.settings().temp_offset,
+            internal: 30,
+            millivolts: self.current_sensor.millivolts(),
+            ..Sensors::default()
+        }
+    }
+
+    /// Lid moved, its interrupt reports the sensors at once
+    pub fn set_lid(&mut self, closed: bool) {
+        self.lid = closed;
+        self.dispatch(Event::Sensors(self.sensors()));
+        self.update_display();
+    }
+
+    /// Boot notice, shown over the current state
+    pub fn notice(&mut self, page: Page) {
+        let state = self.state.take().expect("state is always put back");
+        let (state, actions) = crate::state::notice(state, page, self.settings(), &self.executor.statistics);
+        self.state = Some(state);
+        self.execute_all(actions);
+    }
+
+    pub fn dispatch(&mut self, event: Event) {
+        let state = self.state.take().expect("state is always put back");
+        let (state, actions) = crate::state::transition(state, event, self.settings(), &self.executor.statistics);
+        self.state = Some(state);
+        self.execute_all(actions);
+    }
+
+    /// Executes the actions by the `StateManager` executor, checking that the transition had room for all of them
+    fn execute_all(&mut self, actions: Actions) {
+        assert!(actions.len() < MAX_ACTIONS, "{} actions in {}, the last ones might be lost", actions.len(), self.state_name());
+        self.log.extend(actions.iter().cloned());
+        self.executor.execute_all(actions);
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.executor.hw.settings
+    }
+
+    /// Timer, seconds
+    pub fn time(&self) -> u16 {
+        self.executor.time
+    }
+
+    pub fn heater(&self) -> bool {
+        self.executor.hw.heater.high
+    }
+
+    pub fn motor(&self) -> bool {
+        !self.executor.hw.motor.high //Motor enable is inverted
+    }
+
+    pub fn cook_led(&self) -> bool {
+        self.executor.hw.cook_ld.high
+    }
+
+    /// Oven temperature as shown, quantized by the encoder step
+    fn temp_actual(&self) -> u16 {
+        let unit = self.settings().unit;
+        let step = unit.step();
+        let measured = unit.from_celsius(self.thermocouple as f32);
+        let temp_requested = self.executor.temp_requested;
+        if (temp_requested as f32 - measured).abs() <= step as f32 {
+            temp_requested
+        } else {
+            ((measured / step as f32) as u16) * step
+        }
+    }
+
+    fn update_display(&mut self) {
+        if !self.state().shows_state() {
+            return;
+        }
+        let (temp_actual, running) = (self.temp_actual(), self.state().is_running());
+        self.executor.update_display(temp_actual, running);
+    }
+
+    /// Tick event with the current timer and temperatures
+    pub fn tick_event(&self) -> Tick {
+        let executor = &self.executor;
+        Tick { temp_actual: self.thermocouple.max(0) as u16, temp_requested: executor.unit().to_celsius(executor.temp_requested), time: executor.time, manual: executor.manual, now: self.now }
+    }
+
+    /// State update period: the sensors followed by the tick, as the `StateManager` polls them
+    pub fn tick(&mut self) {
+        if self.adc {
+            let millivolts = IDLE_MILLIVOLTS + if self.motor() { MOTOR_MILLIVOLTS } else { 0 };
+            self.current_sensor.add_value(millivolts as f32 / 1000.0);
+        }
+        self.dispatch(Event::Sensors(self.sensors()));
+        self.dispatch(Event::Tick(self.tick_event()));
+        self.now += TICK_MS;
+        self.update_display();
+    }
+
+    pub fn ticks(&mut self, count: u32) {
+        (0..count).for_each(|_| self.tick());
+    }
+
+    pub fn seconds(&mut self, seconds: u32) {
+        self.ticks(seconds * TICKS_PER_SECOND);
+    }
+
+    pub fn press(&mut self, gesture: Gesture) {
+        self.dispatch(Event::Button(gesture));
+        self.update_display();
+    }
+
+    /// Menu navigation and value change
+    pub fn turn(&mut self, navigation: i16, value: i16) {
+        self.dispatch(Event::Encoders { navigation, value });
+    }
+
+    /// Sets the timer, as the time encoder would
+    pub fn set_timer(&mut self, time: u16, manual: bool) {
+        self.executor.time = time;
+        self.executor.manual = manual;
+        self.update_display();
+    }
+
+    pub fn set_temp(&mut self, temp: u16) {
+        self.executor.temp_requested = temp;
+        self.update_display();
+    }
+
+    pub fn screen(&self) -> [std::string::String; LINES] {
+        let screen = self.executor.hw.display.backend();
+        [screen.text(0), screen.text(1)]
+    }
+}
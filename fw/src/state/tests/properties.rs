@@ -0,0 +1,134 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+use crate::button::Gesture;
+use crate::settings::Settings;
+use crate::state::{Action, Event, Sensors};
+use crate::state::tests::TestOven;
+use crate::temp_sensor::BoardHeat;
+
+const PID_TICKS: u16 = 10; //Controller runs on its own period
+
+/**
+User input, sensor change or time passing, applied to the oven.
+ */
+#[derive(Clone, Debug)]
+enum Op {
+    Ticks(u16),
+    Press(u8), //Short, long or double
+    Lid(bool),
+    Timer(u16, bool),
+    Temp(u16),
+    Oven(i16), //Thermocouple temperature
+    Fault(u8),
+    Encoders(i16, i16),
+    Pid
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (1u16..600).prop_map(Op::Ticks),
+        3 => (0u8..3).prop_map(Op::Press),
+        2 => any::<bool>().prop_map(Op::Lid),
+        2 => (0u16..300, any::<bool>()).prop_map(|(time, manual)| Op::Timer(time, manual)),
+        1 => (10u16..=50).prop_map(|step| Op::Temp(step * 5)),
+        1 => (20i16..260).prop_map(Op::Oven),
+        1 => (0u8..5).prop_map(Op::Fault),
+        1 => (-2i16..=2, -2i16..=2).prop_map(|(navigation, value)| Op::Encoders(navigation, value)),
+        3 => Just(Op::Pid)
+    ]
+}
+
+fn apply(oven: &mut TestOven, op: &Op) {
+    match *op {
+        Op::Ticks(count) => (0..count).for_each(|tick| {
+            oven.tick();
+            if tick % PID_TICKS == 0 {
+                oven.dispatch(Event::Pid);
+            }
+        }),
+        Op::Press(gesture) => oven.press([Gesture::Short, Gesture::Long, Gesture::Double][gesture as usize]),
+        Op::Lid(closed) => oven.set_lid(closed),
+        Op::Timer(time, manual) => oven.set_timer(time, manual),
+        Op::Temp(temp) => oven.set_temp(temp),
+        Op::Oven(temp) => oven.thermocouple = temp,
+        Op::Fault(kind) => {
+            let healthy = oven.sensors();
+            let sensors = match kind {
+                0 => Sensors { temp_error: true, ..healthy },
+                1 => Sensors { current_error: true, ..healthy },
+                2 => Sensors { board: BoardHeat::Overheat, ..healthy },
+                3 => Sensors { motor_overloaded: true, ..healthy },
+                _ => Sensors { motor_running: !healthy.motor_running, motor_standby: !healthy.motor_standby, ..healthy } //Motor doesn't follow its output
+            };
+            oven.dispatch(Event::Sensors(sensors));
+        },
+        Op::Encoders(navigation, value) => oven.dispatch(Event::Encoders { navigation, value }),
+        Op::Pid => oven.dispatch(Event::Pid)
+    }
+}
+
+/**
+Settings, that change the paths through the states.
+ */
+#[derive(Clone, Debug)]
+struct Paths {
+    auto_resume: bool,
+    cool_limit: u16, //Minutes
+    pause_timeout: u16 //Minutes
+}
+
+fn paths() -> impl Strategy<Value = Paths> {
+    (any::<bool>(), 0u16..3, 1u16..3).prop_map(|(auto_resume, cool_limit, pause_timeout)| Paths { auto_resume, cool_limit, pause_timeout })
+}
+
+fn ready(paths: &Paths) -> TestOven {
+    TestOven::ready(Settings { auto_resume: paths.auto_resume, cool_limit: paths.cool_limit, pause_timeout: paths.pause_timeout, ..Settings::default() })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(300))]
+
+    #[test]
+    fn heater_is_never_on_with_lid_open(paths in paths(), ops in vec(op(), 1..40)) {
+        let mut oven = ready(&paths);
+        for op in &ops {
+            apply(&mut oven, op);
+            prop_assert!(!oven.heater() || oven.lid, "heater is on with the lid open after {:?} in {}", op, oven.state_name());
+        }
+    }
+
+    #[test]
+    fn heater_is_only_on_while_cooking(paths in paths(), ops in vec(op(), 1..40)) {
+        let mut oven = ready(&paths);
+        for op in &ops {
+            apply(&mut oven, op);
+            prop_assert!(!oven.heater() || oven.state_name() == "Cooking", "heater is on in {} after {:?}", oven.state_name(), op);
+        }
+    }
+
+    #[test]
+    fn every_halt_switches_heater_off_first(paths in paths(), ops in vec(op(), 1..40)) {
+        let mut oven = ready(&paths);
+        for op in &ops {
+            let (halted, start) = (oven.state_name() == "Halt", oven.log.len());
+            apply(&mut oven, op);
+            if !halted && oven.state_name() == "Halt" {
+                prop_assert!(matches!(oven.log.get(start), Some(Action::Heater(false))), "halt after {:?} doesn't start with the heater off", op);
+            }
+        }
+    }
+
+    #[test]
+    fn halt_keeps_outputs_off(paths in paths(), ops in vec(op(), 1..40)) {
+        let mut oven = ready(&paths);
+        let mut halted = false;
+        for op in &ops {
+            apply(&mut oven, op);
+            halted = halted || oven.state_name() == "Halt";
+            if halted {
+                prop_assert_eq!(oven.state_name(), "Halt");
+                prop_assert!(!oven.heater() && !oven.motor() && !oven.cook_led(), "output is on in the halt after {:?}", op);
+            }
+        }
+    }
+}
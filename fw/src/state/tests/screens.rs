@@ -0,0 +1,254 @@
+use crate::button::Gesture;
+use crate::recovery::Checkpoint;
+use crate::settings::{Settings, TempUnit};
+use crate::state::{Event, Sensors};
+use crate::state::tests::TestOven;
+
+const UNITS: [TempUnit; 2] = [TempUnit::Celsius, TempUnit::Fahrenheit];
+
+/// Runs the scenario from the Ready state in both units, comparing the final screen
+fn snapshot(scenario: impl Fn(&mut TestOven), celsius: [&str; 2], fahrenheit: [&str; 2]) {
+    for (unit, expected) in UNITS.into_iter().zip([celsius, fahrenheit]) {
+        let mut oven = TestOven::ready(Settings { unit, ..Settings::default() });
+        scenario(&mut oven);
+        assert_eq!(oven.screen(), expected, "°{} screen", unit.symbol());
+    }
+}
+
+/// Sets the timer and the temperature, given in Celsius, and starts the cooking
+fn start(oven: &mut TestOven, time: u16, temp: u16) {
+    oven.set_temp(TempUnit::Celsius.convert(temp, oven.settings().unit));
+    oven.set_timer(time, false);
+    oven.tick();
+    oven.press(Gesture::Short);
+}
+
+#[test]
+fn self_test() {
+    let mut oven = TestOven::new(Settings::default(), None);
+    assert_eq!(oven.screen(), ["████████████████"; 2]);
+    oven.seconds(1);
+    assert_eq!(oven.screen(), ["Self test       ", "Temp sensor     "]);
+    oven.ticks(5);
+    assert_eq!(oven.screen(), ["Self test       ", "Motor           "]);
+    oven.seconds(2);
+    assert_eq!(oven.screen(), ["Self test passed", "Cur:ok Mot:ok   "]);
+}
+
+#[test]
+fn self_test_with_lid_open() {
+    let mut oven = TestOven::new(Settings::default(), None);
+    oven.lid = false;
+    oven.seconds(2);
+    assert_eq!(oven.screen(), ["Self test passed", "Cur:ok Mot:--   "], "motor check is skipped");
+    assert_eq!(oven.state_name(), "SelfTest");
+}
+
+#[test]
+fn self_test_failed() {
+    let mut oven = TestOven::new(Settings::default(), None);
+    oven.adc = false;
+    oven.seconds(2);
+    assert_eq!(oven.screen(), ["Self test failed", "Cur:BAD Mot:--  "]);
+}
+
+#[test]
+fn ready() {
+    snapshot(|_| {},
+        ["    Ready       ", "T00:00 ---/050°C"],
+        ["    Ready       ", "T00:00 ---/120°F"]);
+}
+
+#[test]
+fn pre_run() {
+    snapshot(|oven| {
+        oven.set_temp(TempUnit::Celsius.convert(180, oven.settings().unit));
+        oven.set_timer(90, false);
+        oven.tick();
+    },
+        ["  Press RUN     ", "T01:30 ---/180°C"],
+        ["  Press RUN     ", "T01:30 ---/360°F"]);
+}
+
+#[test]
+fn pre_run_manual() {
+    snapshot(|oven| {
+        oven.set_timer(0, true);
+        oven.tick();
+    },
+        ["  Press RUN     ", "T--:-- ---/050°C"],
+        ["  Press RUN     ", "T--:-- ---/120°F"]);
+}
+
+#[test]
+fn cooking_preheating() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.seconds(1);
+    },
+        ["              ✻↑", "T01:30 ---/180°C"],
+        ["              ✻↑", "T01:30 ---/360°F"]);
+}
+
+#[test]
+fn cooking_minutes_and_seconds() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.thermocouple = 178;
+        oven.seconds(31);
+    },
+        ["████▎         ✻♨", "T01:00 180/180°C"],
+        ["████▎         ✻♨", "T01:00 360/360°F"]);
+}
+
+#[test]
+fn cooking_hours_and_minutes() {
+    snapshot(|oven| {
+        start(oven, 5400, 200);
+        oven.thermocouple = 150;
+        oven.seconds(61); //Progress bar is below a column yet
+    },
+        ["              ✻↑", "T1h29m 150/200°C"],
+        ["              ✻↑", "T1h29m 300/390°F"]);
+}
+
+#[test]
+fn cooking_manual_elapsed_hours() {
+    let checkpoint = Checkpoint { temp: 180, time: 3900, manual: true, preheated: true }; //Resumed after an hour and 5 minutes
+    for (unit, expected) in UNITS.into_iter().zip([["███████       ✻♨", "+1h05m 180/180°C"], ["███████       ✻♨", "+1h05m 340/360°F"]]) {
+        let mut oven = TestOven::new(Settings { unit, ..Settings::default() }, Some(checkpoint));
+        oven.thermocouple = 175;
+        oven.seconds(6);
+        oven.press(Gesture::Short);
+        oven.seconds(2);
+        assert_eq!(oven.screen(), expected, "°{} screen", unit.symbol());
+    }
+}
+
+#[test]
+fn recovery_offer() {
+    let checkpoint = Checkpoint { temp: 180, time: 570, manual: false, preheated: true };
+    for (unit, expected) in UNITS.into_iter().zip([["Resume 10 min?  ", "180°C press RUN "], ["Resume 10 min?  ", "360°F press RUN "]]) {
+        let mut oven = TestOven::new(Settings { unit, ..Settings::default() }, Some(checkpoint));
+        oven.thermocouple = 170;
+        oven.seconds(5);
+        assert_eq!(oven.screen(), expected, "°{} screen", unit.symbol());
+    }
+}
+
+#[test]
+fn paused() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.thermocouple = 178;
+        oven.seconds(31);
+        oven.lid = false;
+        oven.tick();
+    },
+        ["   Paused      ⊓", "T01:00 180/180°C"],
+        ["   Paused      ⊓", "T01:00 360/360°F"]);
+}
+
+#[test]
+fn done() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.thermocouple = 178;
+        oven.seconds(91);
+    },
+        ["    Done!       ", "T00:00 180/180°C"],
+        ["    Done!       ", "T00:00 360/360°F"]);
+}
+
+#[test]
+fn done_summary() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.seconds(94); //Heater isn't driven without the PID events, the motor energy rounds down
+    },
+        ["0.00kWh 0.00    ", "T00:00 ---/180°C"],
+        ["0.00kWh 0.00    ", "T00:00 ---/360°F"]);
+}
+
+#[test]
+fn stopped_summary() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.seconds(10);
+        oven.press(Gesture::Short);
+        oven.seconds(3);
+    },
+        ["0.00kWh 0.00    ", "T01:21 ---/180°C"],
+        ["0.00kWh 0.00    ", "T01:21 ---/360°F"]);
+}
+
+#[test]
+fn halt() {
+    snapshot(|oven| {
+        start(oven, 90, 180);
+        oven.seconds(5);
+        oven.dispatch(Event::Sensors(Sensors { temp_error: true, ..oven.sensors() }));
+        oven.seconds(1);
+    },
+        ["T SENSOR FAILURE", "                "],
+        ["T SENSOR FAILURE", "                "]);
+}
+
+#[test]
+fn menu() {
+    snapshot(|oven| oven.press(Gesture::Long),
+        ["Load preset     ", "None            "],
+        ["Load preset     ", "None            "]);
+}
+
+#[test]
+fn menu_preset_loading() {
+    snapshot(|oven| {
+        oven.press(Gesture::Long);
+        oven.turn(0, 2);
+    },
+        ["Load preset     ", "P2 200°C 0h20m  "],
+        ["Load preset     ", "P2 390°F 0h20m  "]);
+}
+
+#[test]
+fn menu_preset_temperature() {
+    snapshot(|oven| {
+        oven.press(Gesture::Long);
+        oven.turn(12, 0);
+    },
+        ["Preset 1 temp   ", "180°C           "],
+        ["Preset 1 temp   ", "360°F           "]);
+}
+
+#[test]
+fn menu_preset_time() {
+    snapshot(|oven| {
+        oven.press(Gesture::Long);
+        oven.turn(13, 0);
+        oven.turn(0, 19);
+    },
+        ["Preset 1 time   ", "2h05m           "],
+        ["Preset 1 time   ", "2h05m           "]);
+}
+
+#[test]
+fn menu_diagnostics() {
+    let mut oven = TestOven::ready(Settings::default());
+    oven.press(Gesture::Long);
+    oven.turn(100, 0);
+    oven.tick();
+    oven.turn(1, 0);
+    assert_eq!(oven.screen(), ["TC   CJ   ADC   ", "20   30   100mV "], "thermocouple without the calibration offset");
+}
+
+#[test]
+fn service() {
+    let mut oven = TestOven::service(Settings::default());
+    oven.adc = false;
+    oven.seconds(1);
+    assert_eq!(oven.screen()[0], "T20 C30 --mV    ", "ADC isn't sampled yet");
+    oven.adc = true;
+    oven.seconds(1);
+    assert_eq!(oven.screen()[0], "T20 C30 100mV   ");
+}
@@ -0,0 +1,218 @@
+use heapless::String;
+use crate::button::Gesture;
+use crate::buzzer::Fault;
+use crate::recovery::Checkpoint;
+use crate::settings::Settings;
+use crate::state::{Action, Event, Sensors};
+use crate::state::tests::TestOven;
+
+/// Oven brought to the state by the regular use
+fn oven_in(state: &str) -> TestOven {
+    let settings = Settings::default();
+    let mut oven = match state {
+        "SelfTest" => return TestOven::new(settings, None),
+        "Service" => return TestOven::service(settings),
+        "Recovery" => {
+            let mut oven = TestOven::new(settings, Some(Checkpoint { temp: 180, time: 600, manual: false, preheated: true }));
+            oven.thermocouple = 170;
+            oven.seconds(5);
+            return oven;
+        },
+        _ => TestOven::ready(settings)
+    };
+    match state {
+        "Ready" => {},
+        "LidOpen" => oven.set_lid(false),
+        "PreRun" => {
+            oven.set_timer(90, false);
+            oven.tick();
+        },
+        "Cooking" | "Paused" | "Done" | "CoolDown" | "Halt" => {
+            oven.set_timer(90, false);
+            oven.tick();
+            oven.press(Gesture::Short);
+            oven.tick();
+            match state {
+                "Paused" => oven.set_lid(false),
+                "Done" => oven.seconds(91),
+                "CoolDown" => {
+                    oven.seconds(91);
+                    oven.press(Gesture::Short);
+                },
+                "Halt" => oven.dispatch(Event::Sensors(Sensors { temp_error: true, ..oven.sensors() })),
+                _ => {}
+            }
+        },
+        "Menu" => oven.press(Gesture::Long),
+        "Notice" => {
+            let (mut top, bottom): (String<16>, String<16>) = (String::new(), String::new());
+            top.push_str("Notice").unwrap_or_default();
+            oven.notice((top, bottom));
+        },
+        _ => panic!("unknown state {}", state)
+    }
+    assert_eq!(oven.state_name(), state);
+    oven
+}
+
+const EVENTS: [&str; 9] = ["Short", "Long", "Double", "LidOpen", "LidClosed", "Fault", "Tick", "Encoders", "Pid"];
+
+/// Applies the single event, only the state machine is moved, without the tick that follows the sensors in the oven
+fn apply(oven: &mut TestOven, event: &str) {
+    let tick = oven.tick_event();
+    match event {
+        "Short" => oven.press(Gesture::Short),
+        "Long" => oven.press(Gesture::Long),
+        "Double" => oven.press(Gesture::Double),
+        "LidOpen" => oven.set_lid(false),
+        "LidClosed" => oven.set_lid(true),
+        "Fault" => oven.dispatch(Event::Sensors(Sensors { temp_error: true, ..oven.sensors() })),
+        "Tick" => oven.dispatch(Event::Tick(tick)),
+        "Encoders" => oven.dispatch(Event::Encoders { navigation: 1, value: 1 }),
+        "Pid" => oven.dispatch(Event::Pid),
+        _ => panic!("unknown event {}", event)
+    }
+}
+
+/// Next state of each state by the event, in the `EVENTS` order
+const TRANSITIONS: [(&str, [&str; 9]); 13] = [
+    //            Short       Long        Double      LidOpen     LidClosed   Fault   Tick        Encoders    Pid
+    ("SelfTest", ["SelfTest", "SelfTest", "SelfTest", "SelfTest", "SelfTest", "Halt", "SelfTest", "SelfTest", "SelfTest"]),
+    ("Ready",    ["Ready",    "Menu",     "Ready",    "LidOpen",  "Ready",    "Halt", "Ready",    "Ready",    "Ready"]),
+    ("LidOpen",  ["LidOpen",  "LidOpen",  "LidOpen",  "LidOpen",  "Ready",    "Halt", "LidOpen",  "LidOpen",  "LidOpen"]),
+    ("PreRun",   ["Cooking",  "Cooking",  "PreRun",   "LidOpen",  "PreRun",   "Halt", "PreRun",   "PreRun",   "PreRun"]),
+    ("Cooking",  ["Done",     "Done",     "Done",     "Paused",   "Cooking",  "Halt", "Cooking",  "Cooking",  "Cooking"]),
+    ("Menu",     ["Ready",    "Ready",    "Menu",     "Menu",     "Menu",     "Halt", "Menu",     "Menu",     "Menu"]),
+    ("Done",     ["CoolDown", "CoolDown", "CoolDown", "LidOpen",  "Done",     "Halt", "Done",     "Done",     "Done"]),
+    ("Paused",   ["Paused",   "Paused",   "Paused",   "Paused",   "Cooking",  "Halt", "Paused",   "Paused",   "Paused"]),
+    ("CoolDown", ["Ready",    "Ready",    "Ready",    "LidOpen",  "CoolDown", "Halt", "CoolDown", "CoolDown", "CoolDown"]),
+    ("Notice",   ["Ready",    "Ready",    "Ready",    "Notice",   "Notice",   "Halt", "Notice",   "Notice",   "Notice"]),
+    ("Recovery", ["Cooking",  "Ready",    "Ready",    "Recovery", "Recovery", "Halt", "Recovery", "Recovery", "Recovery"]),
+    ("Halt",     ["Halt",     "Halt",     "Halt",     "Halt",     "Halt",     "Halt", "Halt",     "Halt",     "Halt"]),
+    ("Service",  ["Service",  "Service",  "Service",  "Service",  "Service",  "Service", "Service", "Service", "Service"])
+];
+
+#[test]
+fn every_state_and_event() {
+    for (state, next) in TRANSITIONS {
+        for (event, expected) in EVENTS.into_iter().zip(next) {
+            let mut oven = oven_in(state);
+            apply(&mut oven, event);
+            assert_eq!(oven.state_name(), expected, "{} by {}", state, event);
+        }
+    }
+}
+
+#[test]
+fn self_test_pulses_motor() {
+    let oven = TestOven::ready(Settings::default());
+    assert!(oven.log.iter().any(|action| matches!(action, Action::Motor(true))), "motor isn't pulsed");
+    assert!(!oven.log.iter().any(|action| matches!(action, Action::FaultLog(_))));
+}
+
+#[test]
+fn self_test_without_current_samples() {
+    let mut oven = TestOven::new(Settings::default(), None);
+    oven.adc = false;
+    oven.seconds(5);
+    assert_eq!(oven.state_name(), "Notice");
+    assert_eq!(oven.screen(), ["Self test failed", "Current sensor  "]);
+    assert!(oven.log.iter().any(|action| matches!(action, Action::FaultLog(Fault::CurrentSensor))));
+    assert!(!oven.log.iter().any(|action| matches!(action, Action::Motor(true))), "motor is pulsed without the current sensor");
+}
+
+#[test]
+fn cooking_drives_outputs() {
+    let mut oven = oven_in("Cooking");
+    assert!(oven.motor() && oven.cook_led() && !oven.heater());
+    oven.dispatch(Event::Pid); //Cold oven, PID asks for the heat
+    oven.tick();
+    assert!(oven.heater());
+}
+
+#[test]
+fn pause_keeps_session() {
+    let mut oven = oven_in("Cooking");
+    oven.dispatch(Event::Pid);
+    oven.seconds(10);
+    oven.set_lid(false);
+    assert!(!oven.heater() && !oven.motor() && !oven.cook_led());
+    oven.seconds(10);
+    assert_eq!(oven.time(), 80, "pause isn't counted");
+    oven.set_lid(true); //Resumed on the lid closing by default
+    assert_eq!(oven.state_name(), "Cooking");
+    assert!(oven.motor() && oven.cook_led());
+}
+
+#[test]
+fn double_press_resumes_pause() {
+    let mut oven = TestOven::ready(Settings { auto_resume: false, ..Settings::default() });
+    oven.set_timer(90, false);
+    oven.tick();
+    oven.press(Gesture::Short);
+    oven.seconds(10);
+    oven.set_lid(false);
+    oven.set_lid(true);
+    assert_eq!(oven.state_name(), "Paused");
+    oven.press(Gesture::Double);
+    oven.seconds(2);
+    assert_eq!(oven.state_name(), "Cooking");
+    assert_eq!(oven.time(), 79, "session is kept");
+}
+
+#[test]
+fn stop_cools_down() {
+    let mut oven = oven_in("Cooking");
+    oven.dispatch(Event::Pid);
+    oven.tick();
+    oven.press(Gesture::Short);
+    assert_eq!(oven.state_name(), "Done", "summary is shown first");
+    assert!(!oven.heater() && oven.motor() && !oven.cook_led());
+    oven.seconds(6);
+    assert_eq!(oven.state_name(), "CoolDown");
+    assert!(oven.motor());
+    oven.press(Gesture::Short); //Cancels the cool-down
+    assert_eq!(oven.state_name(), "Ready");
+    assert!(!oven.motor());
+
+    let mut oven = TestOven::ready(Settings { cool_limit: 0, ..Settings::default() });
+    oven.set_timer(90, false);
+    oven.tick();
+    oven.press(Gesture::Short);
+    oven.press(Gesture::Short);
+    assert_eq!(oven.state_name(), "Done");
+    assert!(!oven.heater() && !oven.motor() && !oven.cook_led());
+    oven.press(Gesture::Short); //Summary is skipped
+    assert_eq!(oven.state_name(), "Ready");
+}
+
+#[test]
+fn cool_down_energy_is_accounted() {
+    let settings = Settings::default();
+    let mut oven = oven_in("Cooking");
+    oven.thermocouple = 150; //Cool-down runs for its whole limit
+    oven.press(Gesture::Short);
+    assert!(oven.log.iter().any(|action| matches!(action, Action::ClearCheckpoint)), "session is over for the recovery");
+    oven.seconds(6 + settings.cool_limit as u32 * 60 - 1);
+    assert_eq!(oven.state_name(), "CoolDown");
+    assert!(!oven.log.iter().any(|action| matches!(action, Action::Session(_))), "accounted before the fan stops");
+    oven.seconds(1);
+    assert_eq!(oven.state_name(), "Ready");
+    let sessions: Vec<_> = oven.log.iter().filter_map(|action| match action {
+        Action::Session(energy) => Some(energy.watt_hours(&settings)),
+        _ => None
+    }).collect();
+    assert_eq!(sessions, [1], "fan run-on of 5 minutes is 1.7Wh");
+}
+
+#[test]
+fn halt_switches_outputs_off() {
+    let mut oven = oven_in("Cooking");
+    oven.dispatch(Event::Pid);
+    oven.tick();
+    assert!(oven.heater());
+    oven.dispatch(Event::Sensors(Sensors { motor_overloaded: true, ..oven.sensors() }));
+    assert_eq!(oven.state_name(), "Halt");
+    assert!(!oven.heater() && !oven.motor() && !oven.cook_led());
+    assert_eq!(oven.screen()[0], " MOTOR OVERLOAD ");
+}
@@ -0,0 +1,167 @@
+use crate::buzzer::Fault;
+use crate::energy::Energy;
+use crate::reset::ResetCause;
+use crate::settings::Settings;
+#[cfg(not(test))]
+use crate::storage::FlashStorage;
+
+const STATISTICS_VERSION: u16 = 1;
+const STATISTICS_WORDS: usize = 17;
+const REFERENCE_SAMPLES: u16 = 3; //Heat-ups averaged for the reference rate
+const WEAK_HEATER: u32 = 80; //Percents of the reference rate
+
+fn split(value: u32) -> [u16; 2] {
+    [value as u16, (value >> 16) as u16]
+}
+
+fn join(low: u16, high: u16) -> u32 {
+    low as u32 | (high as u32) << 16
+}
+
+
+/**
+Lifetime oven statistics, persisted in the internal flash after every cooking session.
+ */
+#[derive(Clone, Copy, Default)]
+pub struct Statistics {
+    pub sessions: u32,
+    pub energy: u32, //Watt-hours
+    pub cost: u32, //Hundredths of the currency
+    pub last_energy: u16, //Watt-hours of the last session
+    pub last_cost: u16,
+    pub heat_reference: u16, //Learned heat-up rate of the healthy heater, tenths of degree per minute
+    pub heat_average: u16, //Recent heat-up rate trend
+    pub heat_last: u16,
+    pub heat_samples: u16,
+    pub resets: u16, //Resets other than the power-on
+    pub last_reset: u16, //Code of the last `ResetCause` other than the power-on
+    pub faults: u16, //Fault log: halts and failed self tests
+    pub last_fault: u16 //Code of the last `Fault`
+}
+
+impl Statistics {
+    fn from_words(stored: &[u16]) -> Option<Self> {
+        let words: [u16; STATISTICS_WORDS] = stored.try_into().ok()?;
+        if words[0] != STATISTICS_VERSION {
+            return None;
+        }
+        Some(Statistics {
+            sessions: join(words[1], words[2]),
+            energy: join(words[3], words[4]),
+            cost: join(words[5], words[6]),
+            last_energy: words[7],
+            last_cost: words[8],
+            heat_reference: words[9],
+            heat_average: words[10],
+            heat_last: words[11],
+            heat_samples: words[12],
+            resets: words[13],
+            last_reset: words[14],
+            faults: words[15],
+            last_fault: words[16]
+        })
+    }
+
+    fn to_words(self) -> [u16; STATISTICS_WORDS] {
+        let mut words = [0u16; STATISTICS_WORDS];
+        words[0] = STATISTICS_VERSION;
+        words[1..3].copy_from_slice(&split(self.sessions));
+        words[3..5].copy_from_slice(&split(self.energy));
+        words[5..7].copy_from_slice(&split(self.cost));
+        words[7] = self.last_energy;
+        words[8] = self.last_cost;
+        words[9..13].copy_from_slice(&[self.heat_reference, self.heat_average, self.heat_last, self.heat_samples]);
+        words[13] = self.resets;
+        words[14] = self.last_reset;
+        words[15] = self.faults;
+        words[16] = self.last_fault;
+        words
+    }
+
+    /// Accounts the finished cooking session
+    pub fn add_session(&mut self, energy: &Energy, settings: &Settings) {
+        let watt_hours = energy.watt_hours(settings);
+        let cost = energy.cost(settings);
+        self.sessions = self.sessions.saturating_add(1);
+        self.energy = self.energy.saturating_add(watt_hours);
+        self.cost = self.cost.saturating_add(cost);
+        self.last_energy = watt_hours.min(u16::MAX as u32) as u16;
+        self.last_cost = cost.min(u16::MAX as u32) as u16;
+    }
+
+    /// Accounts the measured heat-up rate, first ones are learned as the reference
+    pub fn add_heat_up(&mut self, rate: u16) {
+        self.heat_samples = self.heat_samples.saturating_add(1);
+        self.heat_last = rate;
+        if self.heat_samples <= REFERENCE_SAMPLES {
+            let samples = self.heat_samples as u32;
+            self.heat_reference = ((self.heat_reference as u32 * (samples - 1) + rate as u32) / samples) as u16;
+            self.heat_average = self.heat_reference;
+        } else {
+            self.heat_average = ((self.heat_average as u32 * 3 + rate as u32) / 4) as u16; //Smoothed, so a single slow heat-up isn't reported
+        }
+    }
+
+    /// Forgets the learned reference, after the heater replacement
+    pub fn reset_heat_up(&mut self) {
+        self.heat_reference = 0;
+        self.heat_average = 0;
+        self.heat_last = 0;
+        self.heat_samples = 0;
+    }
+
+    /// Heater needs maintenance, heat-up trend has fallen below the reference
+    pub fn is_heater_weak(&self) -> bool {
+        self.heat_samples > REFERENCE_SAMPLES && (self.heat_average as u32) * 100 < self.heat_reference as u32 * WEAK_HEATER
+    }
+
+    /// Measured rate is too low compared to the reference
+    pub fn is_rate_low(&self, rate: u16) -> bool {
+        self.heat_samples >= REFERENCE_SAMPLES && (rate as u32) * 100 < self.heat_reference as u32 * WEAK_HEATER
+    }
+
+    /// Accounts the unexpected reset, the power-on isn't counted
+    pub fn add_reset(&mut self, cause: ResetCause) {
+        if cause != ResetCause::PowerOn {
+            self.resets = self.resets.saturating_add(1);
+            self.last_reset = cause.code();
+        }
+    }
+
+    pub fn add_fault(&mut self, fault: Fault) {
+        self.faults = self.faults.saturating_add(1);
+        self.last_fault = fault.code();
+    }
+}
+
+#[cfg(not(test))]
+impl Statistics {
+    pub fn load(storage: &FlashStorage) -> Self {
+        storage.load::<STATISTICS_WORDS>().and_then(|words| Statistics::from_words(&words)).unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut FlashStorage) {
+        storage.store(&self.to_words()).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let statistics = Statistics { sessions: 70_000, energy: 123_456, cost: 99, last_energy: 650, heat_reference: 35, faults: 2, last_fault: Fault::MotorOverload.code(), ..Statistics::default() };
+        let loaded = Statistics::from_words(&statistics.to_words()).expect("record of the current version");
+        assert_eq!(loaded.to_words(), statistics.to_words());
+    }
+
+    #[test]
+    fn unknown_layout_is_ignored() {
+        let mut words = Statistics::default().to_words();
+        assert!(Statistics::from_words(&words[..STATISTICS_WORDS - 2]).is_none()); //Current version of a shorter length
+        assert!(Statistics::from_words(&[]).is_none());
+        words[0] = STATISTICS_VERSION + 1;
+        assert!(Statistics::from_words(&words).is_none());
+    }
+}
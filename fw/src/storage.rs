@@ -0,0 +1,191 @@
+use core::ptr;
+use stm32f3xx_hal::pac::{flash, FLASH};
+
+const FLASH_START: u32 = 0x0800_0000;
+const PAGE_SIZE: usize = 2048; //STM32F303K8 flash is organized in 2K pages
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+const MAGIC: u16 = 0x0FE7;
+
+pub const SETTINGS_PAGE: u32 = 31; //Last page of the 64K flash
+pub const STATISTICS_PAGE: u32 = 30;
+pub const RECOVERY_PAGE: u32 = 29; //memory.x keeps the firmware image below it
+const BLANK: u16 = 0xFFFF; //Erased flash
+
+#[derive(Debug)]
+pub enum StorageError {
+    TooLarge,
+    Full,
+    WriteProtected,
+    ProgrammingFailed
+}
+
+/**
+Persistent record storage in a single page of the internal flash.
+
+The record is kept at the beginning of the page as a magic, payload length, payload and checksum half-words,
+so a blank, partially written or resized record is reported as missing.
+Every store erases the whole page, thus it should only be used for the rarely changed data.
+
+Alternatively the page is used as a journal of the fixed size records, each followed by its checksum.
+Records are appended to the blank space without the erase, so the journal can be written any time,
+and the whole journal is cleared at once. Both ways must not be mixed on the same page.
+ */
+pub struct FlashStorage {
+    address: u32
+}
+
+/**
+Flash pages of the oven data.
+ */
+pub struct OvenStorage {
+    pub settings: FlashStorage,
+    pub statistics: FlashStorage,
+    pub recovery: FlashStorage
+}
+
+impl Default for OvenStorage {
+    fn default() -> Self {
+        OvenStorage { settings: FlashStorage::new(SETTINGS_PAGE), statistics: FlashStorage::new(STATISTICS_PAGE), recovery: FlashStorage::new(RECOVERY_PAGE) }
+    }
+}
+
+fn checksum(data: &[u16]) -> u16 {
+    data.iter().fold(MAGIC, |acc, v| acc.rotate_left(1) ^ v)
+}
+
+fn wait_ready(flash: &flash::RegisterBlock) -> Result<(), StorageError> {
+    while flash.sr.read().bsy().is_active() {}
+    let status = flash.sr.read();
+    let result = if status.wrprterr().is_error() {
+        Err(StorageError::WriteProtected)
+    } else if status.pgerr().is_error() {
+        Err(StorageError::ProgrammingFailed)
+    } else {
+        Ok(())
+    };
+    flash.sr.write(|w| w.eop().reset().pgerr().reset().wrprterr().reset()); //Flags are cleared by writing 1
+    result
+}
+
+impl FlashStorage {
+    pub const fn new(page: u32) -> Self {
+        FlashStorage { address: FLASH_START + page * PAGE_SIZE as u32 }
+    }
+
+    fn read_halfword(&self, offset: usize) -> u16 {
+        unsafe { ptr::read_volatile((self.address as *const u16).add(offset)) }
+    }
+
+    /// Reads the stored record, returns None if there is no valid record of the requested size
+    pub fn load<const N: usize>(&self) -> Option<[u16; N]> {
+        self.load_up_to::<N>().filter(|(_, length)| *length == N).map(|(data, _)| data)
+    }
+
+    /// Reads the stored record of up to `N` half-words, returns it with its length. The rest of the data is zeroed.
+    pub fn load_up_to<const N: usize>(&self) -> Option<([u16; N], usize)> {
+        let length = self.read_halfword(1) as usize;
+        if self.read_halfword(0) != MAGIC || length > N {
+            return None;
+        }
+        let mut data = [0u16; N];
+        for (index, value) in data.iter_mut().take(length).enumerate() {
+            *value = self.read_halfword(index + 2);
+        }
+        if self.read_halfword(length + 2) == checksum(&data[..length]) {
+            Some((data, length))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the last valid record of the journal
+    pub fn last<const N: usize>(&self) -> Option<[u16; N]> {
+        let mut last = None;
+        for slot in 0..PAGE_SIZE / 2 / (N + 1) {
+            let offset = slot * (N + 1);
+            if (offset..offset + N + 1).all(|index| self.read_halfword(index) == BLANK) {
+                break;
+            }
+            let mut data = [0u16; N];
+            for (index, value) in data.iter_mut().enumerate() {
+                *value = self.read_halfword(offset + index);
+            }
+            if self.read_halfword(offset + N) == checksum(&data) {
+                last = Some(data);
+            }
+        }
+        last
+    }
+
+    /// Appends the record to the journal. Only programs the blank half-words, so it is quick enough to be called any time.
+    pub fn append<const N: usize>(&mut self, data: &[u16; N]) -> Result<(), StorageError> {
+        let slot = (0..PAGE_SIZE / 2 / (N + 1))
+            .find(|slot| (slot * (N + 1)..(slot + 1) * (N + 1)).all(|index| self.read_halfword(index) == BLANK))
+            .ok_or(StorageError::Full)?;
+        let offset = slot * (N + 1);
+        let flash = self.unlock();
+        let result = data.iter().enumerate().try_for_each(|(index, value)| self.program(flash, offset + index, *value))
+            .and_then(|_| self.program(flash, offset + N, checksum(data)));
+        flash.cr.modify(|_, w| w.lock().lock());
+        result
+    }
+
+    /// Erases the journal, if there is anything written. Blocks for the page erase time, so never call it while heater is controlled.
+    pub fn clear(&mut self) -> Result<(), StorageError> {
+        if (0..PAGE_SIZE / 2).all(|index| self.read_halfword(index) == BLANK) {
+            return Ok(());
+        }
+        let flash = self.unlock();
+        let result = self.erase(flash);
+        flash.cr.modify(|_, w| w.lock().lock());
+        result
+    }
+
+    fn unlock(&self) -> &'static flash::RegisterBlock {
+        let flash = unsafe { &*FLASH::ptr() };
+        if flash.cr.read().lock().is_locked() {
+            flash.keyr.write(|w| w.fkeyr().bits(KEY1));
+            flash.keyr.write(|w| w.fkeyr().bits(KEY2));
+        }
+        flash
+    }
+
+    /// Replaces the stored record. Blocks for the page erase time, so never call it while heater is controlled.
+    pub fn store<const N: usize>(&mut self, data: &[u16; N]) -> Result<(), StorageError> {
+        if (N + 3) * 2 > PAGE_SIZE {
+            return Err(StorageError::TooLarge);
+        }
+        if self.load::<N>().map(|stored| stored == *data).unwrap_or(false) {
+            return Ok(()); //Nothing changed, save the flash from wearing
+        }
+
+        let flash = self.unlock();
+        let result = self.erase(flash)
+            .and_then(|_| self.program(flash, 0, MAGIC))
+            .and_then(|_| self.program(flash, 1, N as u16))
+            .and_then(|_| data.iter().enumerate().try_for_each(|(index, value)| self.program(flash, index + 2, *value)))
+            .and_then(|_| self.program(flash, N + 2, checksum(data)));
+
+        flash.cr.modify(|_, w| w.lock().lock());
+        result
+    }
+
+    fn erase(&self, flash: &flash::RegisterBlock) -> Result<(), StorageError> {
+        wait_ready(flash)?;
+        flash.cr.modify(|_, w| w.per().set_bit());
+        flash.ar.write(|w| w.far().bits(self.address));
+        flash.cr.modify(|_, w| w.strt().set_bit());
+        let result = wait_ready(flash);
+        flash.cr.modify(|_, w| w.per().clear_bit());
+        result
+    }
+
+    fn program(&self, flash: &flash::RegisterBlock, offset: usize, value: u16) -> Result<(), StorageError> {
+        flash.cr.modify(|_, w| w.pg().set_bit());
+        unsafe { ptr::write_volatile((self.address as *mut u16).add(offset), value) };
+        let result = wait_ready(flash);
+        flash.cr.modify(|_, w| w.pg().clear_bit());
+        result
+    }
+}
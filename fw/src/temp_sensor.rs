@@ -1,17 +1,48 @@
 use heapless::Deque;
 use max31855::{Max31855, Unit};
-use crate::board::{SpiBus, TcCs};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
 
 type ValuesRing = Deque<f32, 10>;
 
-const TEMP_OFFSET: f32 = 5.0;
+const BOARD_HYSTERESIS: f32 = 3.0; //Degrees of Celsius the board has to cool down by to leave the band
 
-pub struct TempSensor {
-    tc_cs: TcCs,
-    tc_spi: SpiBus,
+/**
+Board temperature band, measured by the thermocouple driver die, which sits next to the TRIACs.
+ */
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum BoardHeat {
+    #[default]
+    Normal,
+    Warning,
+    /// Heater duty has to be limited
+    Derating,
+    Overheat
+}
+
+/**
+Board temperature thresholds of the bands, degrees of Celsius.
+ */
+#[derive(Clone, Copy)]
+pub struct BoardLimits {
+    pub warning: u16,
+    pub derating: u16,
+    pub halt: u16
+}
+
+/**
+MAX31855 thermocouple driver readings, averaged, with the board temperature bands of its die.
+ */
+pub struct TempSensor<SPI, CS> {
+    tc_cs: CS,
+    tc_spi: SPI,
     sensor_values: ValuesRing,
     internal_values: ValuesRing,
-    error: u8
+    raw: Option<f32>, //Latest thermocouple reading, without the calibration offset
+    offset: f32,
+    error: u8,
+    limits: BoardLimits,
+    board: BoardHeat
 }
 
 fn average(values: &ValuesRing) -> Option<f32>{
@@ -22,9 +53,18 @@ fn average(values: &ValuesRing) -> Option<f32>{
     }
 }
 
-impl TempSensor {
-    pub fn new(tc_cs: TcCs, tc_spi: SpiBus) -> Self {
-        TempSensor{tc_cs, tc_spi, sensor_values: Deque::new(), internal_values: Deque::new(), error: 0}
+impl<SPI: Transfer<u8>, CS: OutputPin> TempSensor<SPI, CS> {
+    pub fn new(tc_cs: CS, tc_spi: SPI) -> Self {
+        TempSensor{tc_cs, tc_spi, sensor_values: Deque::new(), internal_values: Deque::new(), raw: None, offset: 0.0, error: 0, limits: BoardLimits { warning: 50, derating: 55, halt: 60 }, board: BoardHeat::Normal}
+    }
+
+    /// Thermocouple calibration offset, degrees of Celsius
+    pub fn set_offset(&mut self, offset: f32) {
+        self.offset = offset;
+    }
+
+    pub fn set_board_limits(&mut self, limits: BoardLimits) {
+        self.limits = limits;
     }
 
     pub fn poll_sensor(&mut self) {
@@ -33,12 +73,14 @@ impl TempSensor {
                 if self.sensor_values.is_full() {
                     self.sensor_values.pop_front();
                 }
-                self.sensor_values.push_back(v.thermocouple + TEMP_OFFSET).unwrap_or_default();
+                self.sensor_values.push_back(v.thermocouple + self.offset).unwrap_or_default();
+                self.raw = Some(v.thermocouple);
                 if self.internal_values.is_full() {
                     self.internal_values.pop_front();
                 }
                 self.internal_values.push_back(v.internal).unwrap_or_default();
                 self.error = 0;
+                self.update_board();
             },
             Err(_) => self.error += 1
         }
@@ -48,6 +90,11 @@ impl TempSensor {
         average(&self.sensor_values)
     }
     
+    /// Latest thermocouple reading, not averaged and uncorrected by the calibration offset
+    pub fn get_raw(&self) -> Option<f32> {
+        self.raw
+    }
+
     pub fn get_internal_temperature(&self) -> Option<f32> {
         average(&self.internal_values)
     }
@@ -56,8 +103,47 @@ impl TempSensor {
         self.error > 20 //2 consecutive second of unresponsive sensor means error
     }
 
+    fn band(&self, temp: f32) -> BoardHeat {
+        let limits = self.limits;
+        if temp >= limits.halt as f32 {
+            BoardHeat::Overheat
+        } else if temp >= limits.derating as f32 {
+            BoardHeat::Derating
+        } else if temp >= limits.warning as f32 {
+            BoardHeat::Warning
+        } else {
+            BoardHeat::Normal
+        }
+    }
+
+    /// Moves the board between the bands, it only goes down once cooled below the band threshold by the hysteresis
+    fn update_board(&mut self) {
+        if let Some(temp) = average(&self.internal_values) {
+            let band = self.band(temp);
+            self.board = if band >= self.board { band } else { self.board.min(self.band(temp + BOARD_HYSTERESIS)) };
+        }
+    }
+
+    pub fn board_heat(&self) -> BoardHeat {
+        self.board
+    }
+
+    /// Heater duty limit in percents, falls linearly from the full duty at the derating threshold to zero at the halt one
+    pub fn duty_limit(&self) -> u8 {
+        if self.board < BoardHeat::Derating {
+            return 100;
+        }
+        let Some(temp) = average(&self.internal_values) else {
+            return 100;
+        };
+        let (low, high) = (self.limits.derating as f32, self.limits.halt as f32);
+        if high <= low {
+            return 0;
+        }
+        (100.0 * (high - temp) / (high - low)).clamp(0.0, 100.0) as u8
+    }
+
     pub fn is_overheating(&self) -> bool {
-        //defmt::println!("Inner temp: {}", average(&self.internal_values));
-        average(&self.internal_values).map(|v| v> 60.0).unwrap_or(false) //60 on the thermocouple driver means that ambient temperature is too high for TRIACs
+        self.board == BoardHeat::Overheat //Ambient temperature is too high for TRIACs
     }
 }
\ No newline at end of file